@@ -21,6 +21,7 @@ export default async function({obj_dir, lib_dir, bin_dir, inc_dirs = [], cdefs =
             let {status, output, error} = await exec({
                 cmd: "gcc",
                 args: [...cflags_full, "-c", "-o", obj, src],
+                rule: this,
             });
             if (output) {
                 console.warn(`cc output: ${output}`);
@@ -31,7 +32,7 @@ export default async function({obj_dir, lib_dir, bin_dir, inc_dirs = [], cdefs =
             if (status && status != 0) {
                 throw new Error(`Error when compiling ${src} Status: ${status}`);
             }
-        });
+        }, {signature: cflags_full});
         return obj;
     }
 
@@ -46,6 +47,7 @@ export default async function({obj_dir, lib_dir, bin_dir, inc_dirs = [], cdefs =
             let {status, output, error} = await exec({
                 cmd: "gcc-ar",
                 args: ["cr", lib, ...objs],
+                rule: this,
             });
             if (output) {
                 console.warn(`ar output: ${output}`);
@@ -72,6 +74,7 @@ export default async function({obj_dir, lib_dir, bin_dir, inc_dirs = [], cdefs =
             let {status, output, error} = await exec({
                 cmd: "gcc",
                 args: ["-o", bin, ...objs, ...libs_flags],
+                rule: this,
             });
             if (output) {
                 console.warn(`cc output: ${output}`);
@@ -82,7 +85,7 @@ export default async function({obj_dir, lib_dir, bin_dir, inc_dirs = [], cdefs =
             if (status && status != 0) {
                 throw new Error(`Error when linking ${name} Status: ${status}`);
             }
-        });
+        }, {signature: libs_flags});
         return bin;
     }
 
@@ -9,18 +9,67 @@ declare module "gear" {
 
     class AnyRule {
         inputs: Input[];
+        implicitInputs: Input[];
+        orderInputs: Input[];
         readonly outputs: Output[];
+        pool?: string;
+        readonly depfile?: string;
+        readonly depfileFormat?: DepfileFormat;
+        readonly discoveredInputs: Input[];
+        readonly sandbox?: import("system").Sandbox;
+        timeout?: number;
+        retries: number;
+        retryDelay: number;
     }
 
-    function Rule(inputs: Input[], outputs: Output[], func?: (this: AnyRule) => Promise<void>): AnyRule;
-    function Rule(outputs: Output[], inputs: Input[], func?: (this: AnyRule) => Promise<void>): AnyRule;
-    function Rule(func: (this: AnyRule) => Promise<void>, outputs: Output[], inputs: Input[]): AnyRule;
+    interface RuleOptions {
+        pool?: string;
+        implicitInputs?: Input[];
+        orderInputs?: Input[];
+        depfile?: string;
+        depfileFormat?: DepfileFormat;
+        override?: boolean;
+        timeout?: number;
+        retries?: number;
+        retryDelay?: number;
+        /// Values which affects outputs of rule function
+        /// The function source is tracked only so the values which is captured by function (like flags) must be passed here
+        signature?: string | string[];
+        toolchain?: object;
+    }
+
+    type DepfileFormat = "make" | "d";
+
+    interface RuleResult {
+        diagnostics?: object[];
+        inputs?: Input[];
+    }
+
+    function Rule(inputs: Input[], outputs: Output[], func?: (this: AnyRule) => Promise<void | RuleResult>, opts?: RuleOptions): AnyRule;
+    function Rule(outputs: Output[], inputs: Input[], func?: (this: AnyRule) => Promise<void | RuleResult>, opts?: RuleOptions): AnyRule;
+    function Rule(func: (this: AnyRule) => Promise<void | RuleResult>, outputs: Output[], inputs: Input[], opts?: RuleOptions): AnyRule;
 
     class Goal {
         inputs: Input[];
         readonly input: Input;
     }
 
+    interface RuleStats {
+        readonly rule: string;
+        readonly outputs: string[];
+        readonly runs: number;
+        readonly failures: number;
+        readonly flips: number;
+        readonly average: number;
+        readonly max: number;
+    }
+
+    interface HistoryStats {
+        readonly builds: number;
+        readonly slowest: RuleStats[];
+        readonly flakiest: RuleStats[];
+    }
+
     class Directory {
         readonly path: string;
         readonly parent?: Directory;
@@ -35,7 +84,10 @@ declare module "gear" {
         scope(name: string, description?: string): Scope;
         input(name: string): Input;
         output(name: string): Output;
-        goal(name: string, description?: string, cb?: (this: Goal) => Promise<void>);
-        goal(name: string, cb: (this: Goal) => Promise<void>, description?: string);
+        goal(name: string, description?: string, opts?: RuleOptions);
+        goal(name: string, description?: string, cb?: (this: Goal) => Promise<void>, opts?: RuleOptions);
+        goal(name: string, cb: (this: Goal) => Promise<void | RuleResult>, description?: string);
+        pool(name: string, depth: number): void;
+        history(builds?: number, limit?: number): Promise<HistoryStats>;
     }
 }
@@ -1,9 +1,12 @@
 use crate::system::{access, modified, AccessMode, Path};
 use crate::{
-    qjs, Mut, Ref, Result, Rule, RuleState, Set, Time, Weak, WeakElement, WeakKey, WeakSet,
+    qjs, Cache, Database, History, Map, Mut, Ref, Result, Rule, RuleState, Set, Time, Weak,
+    WeakElement, WeakKey, WeakSet,
 };
 use derive_deref::Deref;
 use either::{Left, Right};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::Serialize;
 use std::{
     borrow::Borrow,
     collections::VecDeque,
@@ -14,7 +17,8 @@ use std::{
     marker::PhantomData,
 };
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, qjs::FromJs, qjs::IntoJs)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, qjs::FromJs, qjs::IntoJs)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum ArtifactType {
     Source,
@@ -31,7 +35,8 @@ impl Display for ArtifactType {
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, qjs::FromJs, qjs::IntoJs)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, qjs::FromJs, qjs::IntoJs)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum ArtifactKind {
     Actual,
@@ -48,9 +53,92 @@ impl Display for ArtifactKind {
     }
 }
 
+/// The reason why rule is scheduled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The goal is phony so it is processed always
+    Phony,
+    /// The dependency is scheduled too
+    Dependency(String),
+    /// The output does not exist
+    MissingOutput,
+    /// The output is changed since it was produced
+    ChangedOutput { time: Time, recorded: Time },
+    /// The signature of command is changed
+    ChangedSignature,
+    /// The input is newer than output
+    NewerInput {
+        input: String,
+        time: Time,
+        output: Time,
+    },
+    /// The input is changed since output was produced
+    ChangedInput {
+        input: String,
+        time: Time,
+        recorded: Time,
+    },
+    /// The input is added since output was produced
+    AddedInput(String),
+    /// The input is removed since output was produced
+    RemovedInput(String),
+    /// The inputs which is discovered by rule is not recorded yet
+    UnknownInputs,
+}
+
+/// Format time as seconds since epoch
+fn fmt_time(time: &Time) -> String {
+    let time = time.duration_since(Time::UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Phony => "goal is phony".fmt(f),
+            Self::Dependency(name) => write!(f, "dependency `{}` is rebuilt", name),
+            Self::MissingOutput => "output is missing".fmt(f),
+            Self::ChangedOutput { time, recorded } => write!(
+                f,
+                "output is changed (time {} but {} recorded)",
+                fmt_time(time),
+                fmt_time(recorded)
+            ),
+            Self::ChangedSignature => "command signature is changed".fmt(f),
+            Self::NewerInput {
+                input,
+                time,
+                output,
+            } => write!(
+                f,
+                "input `{}` is newer (time {}) than output (time {})",
+                input,
+                fmt_time(time),
+                fmt_time(output)
+            ),
+            Self::ChangedInput {
+                input,
+                time,
+                recorded,
+            } => write!(
+                f,
+                "input `{}` is changed (time {} but {} recorded)",
+                input,
+                fmt_time(time),
+                fmt_time(recorded)
+            ),
+            Self::AddedInput(name) => write!(f, "input `{}` is added", name),
+            Self::RemovedInput(name) => write!(f, "input `{}` is removed", name),
+            Self::UnknownInputs => "discovered inputs is unknown".fmt(f),
+        }
+    }
+}
+
 pub struct Internal {
     name: String,
     description: String,
+    /// The name of scope where goal is declared
+    scope: Mut<Option<String>>,
     rule: Mut<Option<Rule>>,
     time: Mut<Time>,
     type_: ArtifactType,
@@ -216,6 +304,7 @@ where
             Ref::new(Internal {
                 name,
                 description,
+                scope: Default::default(),
                 rule: Default::default(),
                 time: Mut::new(Time::UNIX_EPOCH),
                 type_: U::TYPE,
@@ -332,6 +421,15 @@ impl<U, K> Artifact<U, K> {
         self.0.type_
     }
 
+    /// Get the name of scope where goal is declared
+    pub fn scope(&self) -> Option<String> {
+        self.0.scope.read().clone()
+    }
+
+    pub fn set_scope(&self, scope: impl Into<String>) {
+        *self.0.scope.write() = Some(scope.into());
+    }
+
     pub fn kind(&self) -> ArtifactKind {
         self.0.kind
     }
@@ -360,15 +458,26 @@ impl<U, K> Artifact<U, K> {
         self.kind() == ArtifactKind::Phony
     }
 
+    /// Get all dependencies including order-only inputs
     pub fn inputs(&self) -> impl Iterator<Item = Artifact<Input>> {
         self.0
             .rule
             .read()
             .as_ref()
-            .map(|rule| Right(rule.inputs().into_iter()))
+            .map(|rule| Right(rule.deps().into_iter()))
             .unwrap_or(Left(empty()))
     }
 
+    /// Get dependencies which changes causes rebuilding
+    pub fn tracked_inputs(&self) -> Vec<Artifact<Input>> {
+        self.0
+            .rule
+            .read()
+            .as_ref()
+            .map(|rule| rule.tracked_inputs())
+            .unwrap_or_default()
+    }
+
     pub fn state(&self) -> RuleState {
         let rule = self.0.rule.read();
         rule.as_ref().map(|rule| rule.state()).unwrap_or_default()
@@ -460,27 +569,124 @@ impl<U, K> Artifact<U, K> {
         Ok(())
     }
 
-    pub fn process(&self, schedule: &mut impl FnMut(Rule)) -> bool {
-        if self.is_source() {
-            false
-        } else if self
-            .inputs()
-            .map(|dep| dep.process(schedule) || dep.time() > self.time())
-            .fold(self.is_phony(), |pre, flag| pre || flag)
-        {
-            self.schedule_rule(schedule);
-            true
-        } else {
-            false
+    /// Schedule the rules which should be invoked to produce artifact
+    ///
+    /// Returns `true` when artifact will be rebuilt.
+    /// The rules which is already processed will be added to `visited` map with its results to avoid processing it again.
+    pub fn process<'a, F>(
+        &'a self,
+        db: &'a Database,
+        visited: &'a mut Map<Rule, bool>,
+        schedule: &'a mut F,
+    ) -> LocalBoxFuture<'a, Result<bool>>
+    where
+        F: FnMut(Rule, Reason),
+    {
+        async move {
+            if self.is_source() {
+                return Ok(false);
+            }
+            let rule = self.rule();
+            if let Some(rebuilt) = rule.as_ref().and_then(|rule| visited.get(rule)) {
+                return Ok(*rebuilt);
+            }
+            let deps = self.tracked_inputs();
+            // order-only inputs should be built before but does not cause rebuilding
+            let tracked = deps.iter().collect::<Set<_>>();
+            for dep in self.inputs().filter(|dep| !tracked.contains(dep)) {
+                dep.process(db, visited, schedule).await?;
+            }
+            // all dependencies should be processed
+            let mut rebuilt = None;
+            for dep in &deps {
+                if dep.process(db, visited, schedule).await? && rebuilt.is_none() {
+                    rebuilt = Some(dep);
+                }
+            }
+            let reason = if self.is_phony() {
+                Some(Reason::Phony)
+            } else if let Some(dep) = rebuilt {
+                // the rule will be checked again when dependencies is rebuilt
+                Some(Reason::Dependency(dep.name().clone()))
+            } else if let Some(rule) = &rule {
+                rule.outdated_reason(db).await?
+            } else {
+                None
+            };
+            let rebuilt = if let Some(reason) = reason {
+                self.schedule_rule(reason, schedule);
+                true
+            } else {
+                false
+            };
+            if let Some(rule) = rule {
+                visited.insert(rule, rebuilt);
+            }
+            Ok(rebuilt)
         }
+        .boxed_local()
     }
 
-    fn schedule_rule(&self, schedule: &mut impl FnMut(Rule)) {
+    /// Find dependency cycle which is reachable from this artifact
+    ///
+    /// The artifacts which is already checked will be added to `visited` set to avoid checking it again.
+    pub fn find_cycle(&self, visited: &mut Set<Artifact<Input>>) -> Option<Vec<Artifact<Input>>> {
+        let mut path = Vec::new();
+        Artifact::<Input>(self.0.clone(), PhantomData).find_cycle_from(&mut path, visited)
+    }
+
+    fn schedule_rule(&self, reason: Reason, schedule: &mut impl FnMut(Rule, Reason)) {
         if let Some(rule) = &*self.0.rule.read() {
-            log::trace!("Schedule rule for `{}`", self.name());
-            schedule(rule.clone());
+            log::trace!("Schedule rule for `{}` because {}", self.name(), reason);
+            schedule(rule.clone(), reason);
+        }
+    }
+}
+
+impl Artifact<Input> {
+    fn fmt_cycle_node(&self) -> String {
+        if self.is_phony() {
+            match self.scope().filter(|scope| !scope.is_empty()) {
+                Some(scope) => format!("goal `{}` of scope `{}`", self.name(), scope),
+                None => format!("goal `{}`", self.name()),
+            }
+        } else {
+            format!("`{}`", self.name())
         }
     }
+
+    /// Format dependency cycle in human readable form
+    pub fn fmt_cycle(cycle: &[Artifact<Input>]) -> String {
+        cycle
+            .iter()
+            .map(|artifact| artifact.fmt_cycle_node())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    fn find_cycle_from(
+        &self,
+        path: &mut Vec<Artifact<Input>>,
+        visited: &mut Set<Artifact<Input>>,
+    ) -> Option<Vec<Artifact<Input>>> {
+        if visited.contains(self) {
+            return None;
+        }
+        if let Some(pos) = path.iter().position(|artifact| artifact == self) {
+            let mut cycle = path[pos..].to_vec();
+            cycle.push(self.clone());
+            return Some(cycle);
+        }
+        path.push(self.clone());
+        for dep in self.inputs() {
+            if let Some(cycle) = dep.find_cycle_from(path, visited) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        visited.insert(self.clone());
+        None
+    }
 }
 
 impl<K> From<Artifact<Input, K>> for Artifact<(), K> {
@@ -512,8 +718,37 @@ impl<K> Artifact<Output, K> {
         Artifact(self.0.clone(), PhantomData)
     }
 
-    pub fn set_rule(&self, rule: impl Into<Rule>) {
-        *self.0.rule.write() = Some(rule.into());
+    /// Set the rule which produces artifact
+    ///
+    /// Fails when artifact is already produced by another rule.
+    pub fn set_rule(&self, rule: impl Into<Rule>) -> Result<()> {
+        let rule = rule.into();
+        if let Some(current) = self.rule().filter(|current| current.conflicts(&rule)) {
+            return Err(format!(
+                "Multiple rules produce {} `{}`:\n    {}\n    {}\nUse `override` option to replace rule deliberately.",
+                if self.is_phony() { "goal" } else { "output" },
+                self.name(),
+                current.describe(),
+                rule.describe()
+            )
+            .into());
+        }
+        *self.0.rule.write() = Some(rule);
+        Ok(())
+    }
+
+    /// Replace the rule which produces artifact
+    pub fn override_rule(&self, rule: impl Into<Rule>) {
+        let rule = rule.into();
+        if let Some(current) = self.rule().filter(|current| current.conflicts(&rule)) {
+            log::warn!(
+                "Override rule of `{}`: {} replaced by {}",
+                self.name(),
+                current.describe(),
+                rule.describe()
+            );
+        }
+        *self.0.rule.write() = Some(rule);
     }
 }
 
@@ -560,6 +795,12 @@ pub type ArtifactWeakSet<K> = WeakSet<WeakArtifact<(), K>>;
 pub struct StoreInternal {
     pub actual: Mut<ArtifactWeakSet<Actual>>,
     pub phony: Mut<ArtifactWeakSet<Phony>>,
+    pub database: Database,
+    pub history: History,
+    pub cache: Cache,
+    pub pools: Mut<Map<String, usize>>,
+    #[cfg(all(unix, feature = "jobserver"))]
+    pub jobserver: Mut<Option<crate::JobServer>>,
 }
 
 #[derive(Default, Clone, Deref)]
@@ -569,6 +810,48 @@ impl ArtifactStore {
     pub fn reset(&self) {
         *self.0.actual.write() = Default::default();
         *self.0.phony.write() = Default::default();
+        *self.0.pools.write() = Default::default();
+    }
+
+    /// Declare pool which limits number of rules processed simultaneously
+    pub fn new_pool(&self, name: impl Into<String>, depth: usize) -> Result<()> {
+        let name = name.into();
+        if depth == 0 {
+            return Err(format!("Depth of pool `{}` should be positive", name).into());
+        }
+        let mut pools = self.0.pools.write();
+        match pools.get(&name) {
+            Some(current) if *current != depth => {
+                Err(format!("Pool `{}` already exists with depth {}", name, current).into())
+            }
+            _ => {
+                pools.insert(name, depth);
+                Ok(())
+            }
+        }
+    }
+
+    /// Collect the rules which is needed to build goals
+    ///
+    /// The inputs of goals is traversed recursively including order-only ones.
+    pub fn goal_rules<U, K>(&self, goals: impl IntoIterator<Item = Artifact<U, K>>) -> Set<Rule> {
+        let mut queue = goals
+            .into_iter()
+            .map(|goal| Artifact::<Input>(goal.0, PhantomData))
+            .collect::<Vec<_>>();
+        let mut visited = Set::default();
+        let mut rules = Set::default();
+        while let Some(artifact) = queue.pop() {
+            if visited.contains(&artifact) {
+                continue;
+            }
+            if let Some(rule) = artifact.rule() {
+                rules.insert(rule);
+            }
+            queue.extend(artifact.inputs());
+            visited.insert(artifact);
+        }
+        rules
     }
 
     pub fn fmt_dot<F>(&self, matcher: F, f: &mut Formatter) -> FmtResult
@@ -706,3 +989,211 @@ mod js {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{NoRule, RuleOptions};
+
+    fn goal(store: &ArtifactStore, name: &str, scope: &str) -> Artifact<Output, Phony> {
+        let goal = Artifact::new(store, name, "").unwrap();
+        goal.set_scope(scope);
+        goal
+    }
+
+    fn depend<U, K>(output: &Artifact<Output, K>, input: &Artifact<U, K>) {
+        NoRule::new_raw(
+            once(Artifact::<Input>(input.0.clone(), PhantomData)).collect(),
+            once(output.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn cycle() {
+        let store = ArtifactStore::default();
+        let all = goal(&store, "all", "");
+        let lib = goal(&store, "lib.build", "lib");
+        let obj = goal(&store, "lib.obj.build", "lib.obj");
+        depend(&all, &lib);
+        depend(&lib, &obj);
+
+        assert!(all.find_cycle(&mut Set::default()).is_none());
+
+        // the name of goal does not determine its scope
+        let dotted = goal(&store, "v1.2", "");
+        depend(&obj, &dotted);
+        depend(&dotted, &lib);
+
+        let cycle = all.find_cycle(&mut Set::default()).unwrap();
+        assert_eq!(
+            Artifact::<Input>::fmt_cycle(&cycle),
+            "goal `lib.build` of scope `lib` -> goal `lib.obj.build` of scope `lib.obj` -> goal `v1.2` -> goal `lib.build` of scope `lib`"
+        );
+    }
+
+    #[test]
+    fn cycle_visited() {
+        let store = ArtifactStore::default();
+        let obj = Artifact::<Output, Actual>::new(&store, "main.o", "").unwrap();
+        let bin = Artifact::<Output, Actual>::new(&store, "main", "").unwrap();
+        depend(&bin, &obj);
+        depend(&obj, &bin);
+
+        let mut visited = Set::default();
+        let cycle = bin.find_cycle(&mut visited).unwrap();
+        assert_eq!(
+            Artifact::<Input>::fmt_cycle(&cycle),
+            "`main` -> `main.o` -> `main`"
+        );
+    }
+
+    #[async_std::test]
+    async fn process_diamonds() {
+        let store = ArtifactStore::default();
+        // each level is a diamond so the number of paths is doubled
+        let all = goal(&store, "level0", "");
+        let mut top = all.clone();
+        let mut rules = 0;
+        for level in 1..=32 {
+            let left = goal(&store, &format!("left{}", level), "");
+            let right = goal(&store, &format!("right{}", level), "");
+            NoRule::new_raw(
+                vec![left.input().into_kind_any(), right.input().into_kind_any()]
+                    .into_iter()
+                    .collect(),
+                once(top.clone().into_kind_any()).collect(),
+            )
+            .unwrap();
+            let bottom = goal(&store, &format!("level{}", level), "");
+            depend(&left, &bottom);
+            depend(&right, &bottom);
+            top = bottom;
+            rules += 3;
+        }
+
+        let mut scheduled = 0;
+        assert!(all
+            .process(&Database::default(), &mut Map::default(), &mut |_, _| {
+                scheduled += 1
+            })
+            .await
+            .unwrap());
+        // each rule is processed once
+        assert_eq!(scheduled, rules);
+    }
+
+    #[test]
+    fn conflicts() {
+        let store = ArtifactStore::default();
+        let all = goal(&store, "all", "");
+        let lib = goal(&store, "lib", "");
+        depend(&all, &lib);
+        let rule = all.rule().unwrap();
+
+        // the same rule can be set again
+        all.set_rule(rule.clone()).unwrap();
+
+        // another rule conflicts even when it is the same
+        let error = NoRule::new_raw(
+            once(lib.input().into_kind_any()).collect(),
+            once(all.clone().into_kind_any()).collect(),
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert!(error.contains("Multiple rules produce goal `all`"));
+        assert!(error.contains("Use `override` option"));
+        assert_eq!(all.rule().unwrap().id(), rule.id());
+
+        // the rule can be replaced deliberately
+        NoRule::new_opts(
+            Default::default(),
+            once(all.clone().into_kind_any()).collect(),
+            RuleOptions {
+                overrides: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // the identifier is derived from outputs so the inputs is checked
+        assert!(all.inputs().next().is_none());
+    }
+
+    #[test]
+    fn goal_rules() {
+        let store = ArtifactStore::default();
+        let all = goal(&store, "all", "");
+        let test = goal(&store, "test", "");
+        let obj = Artifact::<Output, Actual>::new(&store, "main.o", "").unwrap();
+        let bin = Artifact::<Output, Actual>::new(&store, "main", "").unwrap();
+        let src = Artifact::<Input, Actual>::new(&store, "main.c", "").unwrap();
+        let gen = goal(&store, "gen", "");
+        NoRule::new_raw(
+            Default::default(),
+            once(gen.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        depend(&obj, &src);
+        NoRule::new_opts(
+            once(obj.input().into_kind_any()).collect(),
+            once(bin.clone().into_kind_any()).collect(),
+            RuleOptions {
+                order_inputs: once(gen.input().into_kind_any()).collect(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        NoRule::new_raw(
+            once(bin.input().into_kind_any()).collect(),
+            once(all.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        // the shared dependency is visited once
+        NoRule::new_raw(
+            [bin.input().into_kind_any(), obj.input().into_kind_any()]
+                .iter()
+                .cloned()
+                .collect(),
+            once(test.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+
+        let rules = |goals: &[&Artifact<Output, Phony>]| {
+            let mut names = store
+                .goal_rules(goals.iter().map(|goal| (*goal).clone()))
+                .into_iter()
+                .flat_map(|rule| rule.outputs())
+                .map(|output| output.name().clone())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        // the order-only inputs is needed too
+        assert_eq!(rules(&[&all]), ["all", "gen", "main", "main.o"]);
+        assert_eq!(
+            rules(&[&test, &all]),
+            ["all", "gen", "main", "main.o", "test"]
+        );
+        assert_eq!(rules(&[&gen]), ["gen"]);
+        assert!(store.goal_rules(once(src)).is_empty());
+    }
+
+    #[test]
+    fn new_pool() {
+        let store = ArtifactStore::default();
+        store.new_pool("link", 2).unwrap();
+        // redefinition with same depth is allowed
+        store.new_pool("link", 2).unwrap();
+        assert!(store
+            .new_pool("link", 3)
+            .unwrap_err()
+            .to_string()
+            .contains("Pool `link` already exists with depth 2"));
+        assert!(store
+            .new_pool("test", 0)
+            .unwrap_err()
+            .to_string()
+            .contains("Depth of pool `test` should be positive"));
+    }
+}
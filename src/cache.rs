@@ -0,0 +1,473 @@
+#[cfg(feature = "http-client")]
+use crate::RemoteCache;
+use crate::{
+    system::{create_dir_all, read_file, rename, write_file, Path, PathBuf},
+    DataHasher, Map, Mut, Ref, Result, Time,
+};
+use async_std::{
+    fs::{copy, read_dir, remove_dir_all},
+    stream::StreamExt,
+};
+use derive_deref::Deref;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// The name of manifest file in cache entry directory
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// The manifest of cache entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// The files which is stored in entry
+    pub files: Vec<String>,
+    /// The digests of inputs which was discovered by rule
+    pub discovered: Map<String, String>,
+    /// The time when entry was used last time
+    pub used: Time,
+}
+
+/// The statistics of cache usage
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    /// Number of rules which outputs was restored from cache
+    pub hits: usize,
+    /// Number of rules which outputs was not found in cache
+    pub misses: usize,
+    /// Number of stored entries
+    pub stores: usize,
+    /// Number of evicted entries
+    pub evictions: usize,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{} hit(s), {} miss(es), {} stored, {} evicted",
+            self.hits, self.misses, self.stores, self.evictions
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Internal {
+    dir: Mut<Option<PathBuf>>,
+    max_size: Mut<u64>,
+    #[cfg(feature = "http-client")]
+    remote: Mut<Option<RemoteCache>>,
+    stats: Mut<CacheStats>,
+}
+
+/// The content-addressed cache of rule outputs
+///
+/// Each entry is keyed by digest of rule signature and contents of its inputs.
+/// The least recently used entries will be evicted when local cache exceeds its size.
+/// The remote cache will be used when entry is not found locally.
+#[derive(Default, Clone, Deref)]
+pub struct Cache(Ref<Internal>);
+
+impl Cache {
+    /// Open cache in specified directory
+    ///
+    /// The size is limited by `max_size` in bytes (0 means unlimited).
+    pub async fn open(&self, dir: impl AsRef<Path>, max_size: u64) -> Result<()> {
+        let dir = dir.as_ref();
+        if !dir.is_dir().await {
+            create_dir_all(dir).await?;
+        }
+        log::debug!("Open build cache `{}`", dir.display());
+        *self.0.dir.write() = Some(dir.into());
+        *self.0.max_size.write() = max_size;
+        Ok(())
+    }
+
+    /// Use remote cache in addition to local
+    #[cfg(feature = "http-client")]
+    pub fn set_remote(&self, remote: RemoteCache) {
+        *self.0.remote.write() = Some(remote);
+    }
+
+    #[cfg(feature = "http-client")]
+    fn remote(&self) -> Option<RemoteCache> {
+        self.0.remote.read().clone()
+    }
+
+    /// Check that cache is enabled
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "http-client")]
+        if self.0.remote.read().is_some() {
+            return true;
+        }
+        self.0.dir.read().is_some()
+    }
+
+    /// Get the statistics of cache usage
+    pub fn stats(&self) -> CacheStats {
+        self.0.stats.read().clone()
+    }
+
+    /// Get the key of entry using rule signature and input digests
+    pub fn key<'a>(
+        signature: &str,
+        inputs: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> String {
+        let mut hasher = DataHasher::default();
+        hasher.hash(&signature);
+        for (name, digest) in inputs {
+            hasher.hash(name);
+            hasher.hash(digest);
+        }
+        hasher.finish_base64_string()
+    }
+
+    fn entry_dir(&self, key: &str) -> Result<Option<PathBuf>> {
+        if key.len() < 2
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("Invalid cache key `{}`", key).into());
+        }
+        Ok(self
+            .0
+            .dir
+            .read()
+            .as_ref()
+            .map(|dir| dir.join(&key[..2]).join(key)))
+    }
+
+    /// Restore files from entry
+    ///
+    /// Returns the digests of discovered inputs of restored entry.
+    /// Returns `None` when entry is not found or its discovered inputs does not match.
+    /// The entries which has files other than `outputs` is rejected too.
+    pub async fn restore(
+        &self,
+        key: &str,
+        outputs: &[String],
+    ) -> Result<Option<Map<String, String>>> {
+        #[allow(unused_mut)]
+        let mut restored = self.restore_entry(key, outputs).await?;
+        #[cfg(feature = "http-client")]
+        if restored.is_none() {
+            if let Some(remote) = self.remote() {
+                restored = self.restore_remote(&remote, key, outputs).await?;
+            }
+        }
+        {
+            let mut stats = self.0.stats.write();
+            if restored.is_some() {
+                stats.hits += 1;
+            } else {
+                stats.misses += 1;
+            }
+        }
+        Ok(restored)
+    }
+
+    async fn restore_entry(
+        &self,
+        key: &str,
+        outputs: &[String],
+    ) -> Result<Option<Map<String, String>>> {
+        let dir = if let Some(dir) = self.entry_dir(key)? {
+            dir
+        } else {
+            return Ok(None);
+        };
+        let manifest = dir.join(MANIFEST_FILE);
+        if !manifest.is_file().await {
+            return Ok(None);
+        }
+        let mut entry: CacheEntry = serde_json::from_slice(&read_file(&manifest).await?)?;
+        if !Self::declared(key, &entry.files, outputs)
+            || !Self::matches(key, &entry.discovered).await?
+        {
+            return Ok(None);
+        }
+        for (index, name) in entry.files.iter().enumerate() {
+            let path = Path::new(name);
+            if let Some(parent) = path.parent() {
+                if !parent.as_os_str().is_empty() && !parent.is_dir().await {
+                    create_dir_all(parent).await?;
+                }
+            }
+            copy(dir.join(index.to_string()), path).await?;
+        }
+        // mark entry as recently used
+        entry.used = Time::now();
+        write_file(&manifest, serde_json::to_vec(&entry)?).await?;
+        log::debug!("Restore cache entry `{}`", key);
+        Ok(Some(entry.discovered))
+    }
+
+    #[cfg(feature = "http-client")]
+    async fn restore_remote(
+        &self,
+        remote: &RemoteCache,
+        key: &str,
+        outputs: &[String],
+    ) -> Result<Option<Map<String, String>>> {
+        let entry = if let Some(entry) = remote.lookup(key).await? {
+            entry
+        } else {
+            return Ok(None);
+        };
+        if !Self::matches(key, &entry.discovered).await? || !remote.restore(&entry, outputs).await?
+        {
+            return Ok(None);
+        }
+        log::debug!("Restore remote cache entry `{}`", key);
+        // keep entry locally to avoid fetching it again
+        self.store_local(
+            key,
+            entry.files.iter().map(|file| &file.path),
+            entry.discovered.clone(),
+        )
+        .await?;
+        Ok(Some(entry.discovered))
+    }
+
+    /// Check that entry has only the files which is declared as outputs
+    ///
+    /// The entry which may overwrite arbitrary files must not be restored.
+    fn declared(key: &str, files: &[String], outputs: &[String]) -> bool {
+        for file in files {
+            if !outputs.contains(file) {
+                log::warn!("Cache entry `{}` has undeclared output `{}`", key, file);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Check that discovered inputs of entry is not changed
+    async fn matches(key: &str, discovered: &Map<String, String>) -> Result<bool> {
+        for (name, digest) in discovered {
+            let path = Path::new(name);
+            if !path.is_file().await || DataHasher::hash_file_base64_string(path).await? != *digest
+            {
+                log::debug!("Cache entry `{}` does not match input `{}`", key, name);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Store files into entry
+    ///
+    /// The files which does not exists will be skipped.
+    pub async fn store(
+        &self,
+        key: &str,
+        files: &[String],
+        discovered: Map<String, String>,
+    ) -> Result<()> {
+        self.store_local(key, files, discovered.clone()).await?;
+        #[cfg(feature = "http-client")]
+        if let Some(remote) = self.remote() {
+            remote.store(key, files, discovered).await?;
+        }
+        self.0.stats.write().stores += 1;
+        Ok(())
+    }
+
+    async fn store_local<'a>(
+        &self,
+        key: &str,
+        files: impl IntoIterator<Item = &'a String>,
+        discovered: Map<String, String>,
+    ) -> Result<()> {
+        let dir = if let Some(dir) = self.entry_dir(key)? {
+            dir
+        } else {
+            return Ok(());
+        };
+        let tmp = dir.with_extension("tmp");
+        if tmp.is_dir().await {
+            remove_dir_all(&tmp).await?;
+        }
+        create_dir_all(&tmp).await?;
+        let mut entry = CacheEntry {
+            files: Vec::new(),
+            discovered,
+            used: Time::now(),
+        };
+        for name in files {
+            if Path::new(name).is_file().await {
+                copy(name, tmp.join(entry.files.len().to_string())).await?;
+                entry.files.push(name.clone());
+            }
+        }
+        write_file(tmp.join(MANIFEST_FILE), serde_json::to_vec(&entry)?).await?;
+        if dir.is_dir().await {
+            remove_dir_all(&dir).await?;
+        }
+        rename(&tmp, &dir).await?;
+        log::debug!("Store cache entry `{}`", key);
+        Ok(())
+    }
+
+    /// Evict least recently used entries until cache fits its size
+    pub async fn evict(&self) -> Result<()> {
+        let (dir, max_size) = if let Some(dir) = &*self.0.dir.read() {
+            (dir.clone(), *self.0.max_size.read())
+        } else {
+            return Ok(());
+        };
+        if max_size == 0 {
+            return Ok(());
+        }
+
+        let mut entries = Vec::new();
+        let mut total = 0;
+        let mut groups = read_dir(&dir).await?;
+        while let Some(group) = groups.next().await {
+            let group = group?.path();
+            if !group.is_dir().await {
+                continue;
+            }
+            let mut dirs = read_dir(&group).await?;
+            while let Some(entry) = dirs.next().await {
+                let entry = entry?.path();
+                let manifest = entry.join(MANIFEST_FILE);
+                if !manifest.is_file().await {
+                    continue;
+                }
+                // the entries with broken manifest will be evicted first
+                let time = read_file(&manifest)
+                    .await
+                    .ok()
+                    .and_then(|raw| serde_json::from_slice::<CacheEntry>(&raw).ok())
+                    .map(|entry| entry.used)
+                    .unwrap_or(Time::UNIX_EPOCH);
+                let mut size = 0;
+                let mut files = read_dir(&entry).await?;
+                while let Some(file) = files.next().await {
+                    size += file?.metadata().await?.len();
+                }
+                total += size;
+                entries.push((time, size, entry));
+            }
+        }
+
+        if total <= max_size {
+            return Ok(());
+        }
+        entries.sort_by_key(|entry| entry.0);
+        for (_, size, entry) in entries {
+            if total <= max_size {
+                break;
+            }
+            log::debug!("Evict cache entry `{}`", entry.display());
+            remove_dir_all(&entry).await?;
+            total -= size;
+            self.0.stats.write().evictions += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::iter::empty;
+
+    async fn open(dir: &Path, max_size: u64) -> Cache {
+        let cache = Cache::default();
+        cache.open(dir.join("cache"), max_size).await.unwrap();
+        cache
+    }
+
+    #[async_std::test]
+    async fn store_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Path::new(dir.path().to_str().unwrap());
+        let path = |name: &str| dir.join(name).display().to_string();
+        let cache = open(dir, 0).await;
+        assert!(cache.is_enabled());
+
+        write_file(path("main.h"), "header").await.unwrap();
+        write_file(path("main.o"), "object").await.unwrap();
+        let mut discovered = Map::default();
+        discovered.insert(
+            path("main.h"),
+            DataHasher::hash_file_base64_string(path("main.h"))
+                .await
+                .unwrap(),
+        );
+        let key = Cache::key("sig", empty());
+        cache
+            .store(
+                &key,
+                &[path("main.o"), path("missing.o")],
+                discovered.clone(),
+            )
+            .await
+            .unwrap();
+
+        let outputs = [path("main.o"), path("missing.o")];
+        crate::system::remove_file(path("main.o")).await.unwrap();
+        // the entry which has undeclared files is not restored
+        assert!(cache
+            .restore(&key, &[path("main")])
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!Path::new(&path("main.o")).exists().await);
+        // the discovered inputs is returned to be tracked by rule
+        assert_eq!(
+            cache.restore(&key, &outputs).await.unwrap(),
+            Some(discovered)
+        );
+        assert_eq!(read_file(path("main.o")).await.unwrap(), b"object");
+        assert!(!Path::new(&path("missing.o")).exists().await);
+
+        // the entry does not match when discovered input is changed
+        write_file(path("main.h"), "changed").await.unwrap();
+        assert!(cache.restore(&key, &outputs).await.unwrap().is_none());
+        assert!(cache
+            .restore(&Cache::key("other", empty()), &outputs)
+            .await
+            .unwrap()
+            .is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.stores), (1, 3, 1));
+
+        // short keys is rejected instead of panicking
+        assert!(cache.restore("a", &outputs).await.is_err());
+        assert!(cache.restore("", &outputs).await.is_err());
+        assert!(cache.store("../a", &[], Map::default()).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn evict() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = Path::new(dir.path().to_str().unwrap());
+        let path = |name: &str| dir.join(name).display().to_string();
+        // each entry takes about 1.2K so only two entries fits
+        let cache = open(dir, 2600).await;
+
+        let keys = ["a", "b", "c"]
+            .iter()
+            .map(|sig| Cache::key(sig, empty()))
+            .collect::<Vec<_>>();
+        for key in &keys {
+            write_file(path("data"), vec![b'x'; 1024]).await.unwrap();
+            cache
+                .store(key, &[path("data")], Map::default())
+                .await
+                .unwrap();
+        }
+        let outputs = [path("data")];
+        // the first entry becomes recently used
+        assert!(cache.restore(&keys[0], &outputs).await.unwrap().is_some());
+
+        cache.evict().await.unwrap();
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.restore(&keys[0], &outputs).await.unwrap().is_some());
+        assert!(cache.restore(&keys[1], &outputs).await.unwrap().is_none());
+        assert!(cache.restore(&keys[2], &outputs).await.unwrap().is_some());
+    }
+}
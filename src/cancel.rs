@@ -0,0 +1,108 @@
+use crate::{Mut, Ref, Result, Weak};
+use futures::future::{select, Either};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Default)]
+pub struct Internal {
+    cancelled: AtomicBool,
+    wakers: Mut<Vec<Waker>>,
+    children: Mut<Vec<Weak<Internal>>>,
+}
+
+/// The token which is used to cancel processing
+///
+/// Cancelling stops scheduling rules and terminates commands which is executed by running rules.
+#[derive(Clone, Default)]
+pub struct Cancel(Ref<Internal>);
+
+impl Cancel {
+    /// Cancel processing
+    ///
+    /// The child tokens will be cancelled too.
+    pub fn cancel(&self) {
+        if !self.0.cancelled.swap(true, Ordering::SeqCst) {
+            for waker in self.0.wakers.write().drain(..) {
+                waker.wake();
+            }
+            let children = self.0.children.write().drain(..).collect::<Vec<_>>();
+            for child in children {
+                if let Some(child) = child.upgrade() {
+                    Self(child).cancel();
+                }
+            }
+        }
+    }
+
+    /// Create token which will be cancelled with this one but can be cancelled separately
+    pub fn child(&self) -> Self {
+        let child = Self::default();
+        let mut children = self.0.children.write();
+        // checking under lock to not miss concurrent cancellation
+        if self.is_cancelled() {
+            drop(children);
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Ref::downgrade(&child.0));
+        }
+        child
+    }
+
+    /// Check that processing is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait for cancellation
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled(self.clone())
+    }
+
+    /// Run future until it completes or processing is cancelled
+    ///
+    /// The future will be dropped on cancellation.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output> {
+        futures::pin_mut!(future);
+        match select(future, self.cancelled()).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err("Cancelled".into()),
+        }
+    }
+}
+
+impl Debug for Cancel {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_tuple("Cancel").field(&self.is_cancelled()).finish()
+    }
+}
+
+/// The future which resolves when processing is cancelled
+pub struct Cancelled(Cancel);
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.0.is_cancelled() {
+            return Poll::Ready(());
+        }
+        {
+            let mut wakers = (self.0).0.wakers.write();
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+        // check again to not miss cancellation which happens while registering
+        if self.0.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{
+    system::{remove_dir, remove_file, Path},
+    ArtifactStore, Result,
+};
+use std::iter::once;
+
+impl ArtifactStore {
+    /// Check that artifact is produced by some current rule
+    fn is_produced(&self, name: &str) -> bool {
+        self.actual
+            .read()
+            .get(name)
+            .map(|artifact| artifact.rule().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Remove file and its parent directory when it becomes empty
+    async fn remove_output(name: &str) -> Result<()> {
+        let path = Path::new(name);
+        remove_file(path).await?;
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                // directory will not be removed until it is empty
+                if let Err(error) = remove_dir(dir).await {
+                    log::debug!("Keep directory `{}` due to: {}", dir.display(), error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove outputs which was produced before but no current rule produces it
+    ///
+    /// Returns the list of removed files. Nothing will be removed in dry-run mode.
+    pub async fn clean_dead(&self, dry_run: bool) -> Result<Vec<String>> {
+        let dead = self
+            .database
+            .outputs()
+            .into_iter()
+            .filter(|(name, _)| !self.is_produced(name))
+            .collect::<Vec<_>>();
+
+        let mut removed = Vec::new();
+        for (name, entry) in dead {
+            for file in once(&name).chain(entry.side.iter()) {
+                if Path::new(file).is_file().await {
+                    log::debug!("Remove dead output `{}`", file);
+                    if !dry_run {
+                        Self::remove_output(file).await?;
+                    }
+                    removed.push(file.clone());
+                }
+            }
+            if !dry_run {
+                self.database.remove_output(&name);
+            }
+        }
+
+        if !dry_run {
+            self.database.save().await?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Remove outputs of rules which is needed to build goals
+    ///
+    /// All goals will be cleaned when no goals passed.
+    /// Returns the list of removed files. Nothing will be removed in dry-run mode.
+    pub async fn clean_goals<S: AsRef<str>>(
+        &self,
+        goals: &[S],
+        dry_run: bool,
+    ) -> Result<Vec<String>> {
+        let goals = if goals.is_empty() {
+            self.phony.read().iter().collect::<Vec<_>>()
+        } else {
+            goals
+                .iter()
+                .map(|name| {
+                    let name = name.as_ref();
+                    self.phony
+                        .read()
+                        .get(name)
+                        .ok_or_else(|| format!("Unknown goal `{}`", name))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        };
+        let rules = self.goal_rules(goals);
+
+        let mut removed = Vec::new();
+        for rule in rules {
+            let outputs = rule
+                .outputs()
+                .into_iter()
+                .filter(|output| !output.is_phony())
+                .map(|output| output.name().clone())
+                .collect::<Vec<_>>();
+            for file in outputs.iter().chain(rule.side_outputs().iter()) {
+                if Path::new(file).is_file().await {
+                    log::debug!("Remove output `{}`", file);
+                    if !dry_run {
+                        Self::remove_output(file).await?;
+                    }
+                    removed.push(file.clone());
+                }
+            }
+            if !dry_run {
+                for output in &outputs {
+                    self.database.remove_output(output);
+                }
+            }
+        }
+
+        if !dry_run {
+            self.database.save().await?;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        system::write_file, Actual, Artifact, Duration, NoRule, Output, OutputEntry, Phony, Time,
+    };
+
+    fn entry(side: &[String]) -> OutputEntry {
+        OutputEntry {
+            signature: String::default(),
+            time: Time::UNIX_EPOCH,
+            digest: String::default(),
+            duration: Duration::default(),
+            inputs: Default::default(),
+            side: side.to_vec(),
+            discovered: Vec::new(),
+        }
+    }
+
+    /// Create file and declare rule which produces it
+    async fn output(store: &ArtifactStore, name: &str) -> Artifact<Output, Actual> {
+        write_file(name, name).await.unwrap();
+        let output = Artifact::<Output, Actual>::new(store, name, "").unwrap();
+        NoRule::new_raw(
+            Default::default(),
+            once(output.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        output
+    }
+
+    #[async_std::test]
+    async fn clean_dead() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        crate::system::create_dir_all(path("dead")).await.unwrap();
+        for name in &["dead/main.o", "dead/main.d"] {
+            write_file(path(name), "").await.unwrap();
+        }
+
+        let store = ArtifactStore::default();
+        store
+            .database
+            .open(dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let _live = output(&store, &path("live.o")).await;
+        store.database.set_output(path("live.o"), entry(&[]));
+        store
+            .database
+            .set_output(path("dead/main.o"), entry(&[path("dead/main.d")]));
+        store.database.save().await.unwrap();
+
+        let removed = store.clean_dead(true).await.unwrap();
+        assert_eq!(removed, [path("dead/main.o"), path("dead/main.d")]);
+        assert!(Path::new(&path("dead/main.o")).is_file().await);
+        assert!(store.database.output(path("dead/main.o")).is_some());
+
+        let removed = store.clean_dead(false).await.unwrap();
+        assert_eq!(removed, [path("dead/main.o"), path("dead/main.d")]);
+        // empty directory is removed too
+        assert!(!Path::new(&path("dead")).exists().await);
+        assert!(Path::new(&path("live.o")).is_file().await);
+
+        let database = crate::Database::default();
+        database.open(dir.path().to_str().unwrap()).await.unwrap();
+        assert!(database.output(path("dead/main.o")).is_none());
+        assert!(database.output(path("live.o")).is_some());
+    }
+
+    /// Declare goal which depends on outputs
+    fn goal(
+        store: &ArtifactStore,
+        name: &str,
+        inputs: &[&Artifact<Output, Actual>],
+    ) -> Artifact<Output, Phony> {
+        let goal = Artifact::<Output, Phony>::new(store, name, "").unwrap();
+        NoRule::new_raw(
+            inputs
+                .iter()
+                .map(|input| input.input().into_kind_any())
+                .collect(),
+            once(goal.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        goal
+    }
+
+    #[async_std::test]
+    async fn clean_goals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+
+        let store = ArtifactStore::default();
+        store
+            .database
+            .open(dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let main_o = output(&store, &path("main.o")).await;
+        let main = Artifact::<Output, Actual>::new(&store, path("main"), "").unwrap();
+        write_file(path("main"), "").await.unwrap();
+        NoRule::new_raw(
+            once(main_o.input().into_kind_any()).collect(),
+            once(main.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        let test = output(&store, &path("test")).await;
+        let _all = goal(&store, "all", &[&main]);
+        let _test = goal(&store, "test", &[&test]);
+        for name in &["main.o", "main", "test"] {
+            store.database.set_output(path(name), entry(&[]));
+        }
+
+        assert!(store
+            .clean_goals(&["unknown"], false)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown goal `unknown`"));
+
+        let mut removed = store.clean_goals(&["all"], true).await.unwrap();
+        removed.sort();
+        assert_eq!(removed, [path("main"), path("main.o")]);
+        assert!(Path::new(&path("main")).is_file().await);
+        assert!(store.database.output(path("main")).is_some());
+
+        let mut removed = store.clean_goals(&["all"], false).await.unwrap();
+        removed.sort();
+        assert_eq!(removed, [path("main"), path("main.o")]);
+        assert!(!Path::new(&path("main")).exists().await);
+        assert!(!Path::new(&path("main.o")).exists().await);
+        // outputs of other goals is kept
+        assert!(Path::new(&path("test")).is_file().await);
+        assert!(store.database.output(path("main")).is_none());
+        assert!(store.database.output(path("test")).is_some());
+
+        let removed = store.clean_goals::<&str>(&[], false).await.unwrap();
+        assert_eq!(removed, [path("test")]);
+    }
+}
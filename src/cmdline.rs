@@ -12,9 +12,16 @@ const RULES_FILES: &str = "Gearfile, Gearfile.js, Gear.js";
 /// Default config files
 const CONFIG_FILES: &str = "gear.json, gear.yaml, gear.toml";
 
+/// Default size of build cache in megabytes
+const CACHE_SIZE: u64 = 1024;
+
 /// Default config file
 const CONFIG_FILE: &str = "gear.toml";
 
+/// Supported jobserver styles
+#[cfg(all(unix, feature = "jobserver"))]
+const JOBSERVER_STYLE_VALUES: &[&str] = &["pipe", "fifo"];
+
 #[cfg(unix)]
 const PATHS_DELIMITER: &str = ":";
 
@@ -93,9 +100,28 @@ pub struct Args {
     #[structopt(name = "jobs", short = "j", long = "jobs")]
     pub jobs: Option<usize>,
 
+    /// Jobserver style
+    ///
+    /// The kind of jobserver which is created for child processes: `pipe` passes inherited descriptors (any GNU make) and `fifo` passes named pipe (GNU make 4.4 and newer).
+    #[cfg(all(unix, feature = "jobserver"))]
+    #[structopt(
+        name = "style",
+        long = "jobserver-style",
+        default_value = "pipe",
+        possible_values = JOBSERVER_STYLE_VALUES,
+    )]
+    pub jobserver_style: gear::JobServerStyle,
+
+    /// Keep going when some rules fails
+    ///
+    /// Do not stop scheduling rules until N rules fails (0 or no value means infinity).
+    /// By default the scheduling will be stopped after first failure.
+    #[structopt(name = "N", short = "k", long = "keep-going", require_equals = true)]
+    pub keep_going: Option<Option<usize>>,
+
     /// Print database
     ///
-    /// Prints known goals and variables.
+    /// Prints known goals and variables as plain tree, rules graph in Graphviz format or both in JSON.
     /// You can use pattern to filter printed data.
     #[structopt(
         name = "format",
@@ -106,12 +132,94 @@ pub struct Args {
     )]
     pub print_db: Option<Option<Print>>,
 
+    /// Query artifacts
+    ///
+    /// Prints artifacts which matches expression like `deps(all)`, `rdeps(all, src/main.h)`, `somepath(all, src/main.c)`, `kind(phony, *)` or `type(source, deps(all))`.
+    /// Results can be combined using `+`, `-` and `^` operators.
+    #[structopt(name = "expr", short = "q", long = "query")]
+    pub query: Option<String>,
+
+    /// Format of query results
+    #[structopt(
+        name = "output",
+        long = "query-output",
+        default_value = "names",
+        possible_values = QUERY_OUTPUT_VALUES,
+    )]
+    pub query_output: QueryOutput,
+
+    /// Print build history
+    ///
+    /// Prints the slowest and the flakiest rules across last N builds (10 by default).
+    #[structopt(name = "builds", long = "history", require_equals = true)]
+    pub history: Option<Option<usize>>,
+
+    /// Clean goals
+    ///
+    /// Removes the outputs of rules which is needed to build goals (all goals when no goals passed).
+    /// Use with `-n` flag to list files without removing.
+    #[structopt(long = "clean")]
+    pub clean: bool,
+
+    /// Remove dead outputs
+    ///
+    /// Removes files which was produced before but no current rule produces it.
+    /// Use with `-n` flag to list files without removing.
+    #[structopt(long = "clean-dead")]
+    pub clean_dead: bool,
+
     /// Do not invoke rules
     ///
     /// Check consistency only
     #[structopt(short = "n", long = "dry-run")]
     pub dry_run: bool,
 
+    /// Explain rebuilding
+    ///
+    /// Reports the reason why each scheduled rule should be invoked (missing output, newer input, changed signature and so on).
+    /// Use with `-n` flag to explain without invoking.
+    #[structopt(long = "explain")]
+    pub explain: bool,
+
+    /// Build cache directory
+    ///
+    /// Restores outputs of native rules from cache instead of invoking when inputs is not changed.
+    #[structopt(name = "dir", long = "cache")]
+    pub cache: Option<PathBuf>,
+
+    /// Build cache size
+    ///
+    /// Least recently used entries will be evicted when cache exceeds size in megabytes (0 means unlimited).
+    #[structopt(name = "size", long = "cache-size")]
+    pub cache_size: Option<u64>,
+
+    /// Remote build cache URL
+    ///
+    /// Shares outputs of native rules through HTTP cache server which speaks Bazel remote cache protocol.
+    #[cfg(feature = "http-client")]
+    #[structopt(name = "url", long = "remote-cache")]
+    pub remote_cache: Option<String>,
+
+    /// Do not upload to remote build cache
+    #[cfg(feature = "http-client")]
+    #[structopt(long = "remote-cache-read-only")]
+    pub remote_cache_read_only: bool,
+
+    /// Execute rules in sandbox
+    ///
+    /// Native and `exec`-based rules will see only system and toolchain directories and declared inputs read-only and directories of outputs writable.
+    /// The undeclared paths is not visible to commands and the accesses to it by failed commands will be reported as warnings.
+    /// The undeclared inputs inside system directories like `/usr` and `/etc` cannot be detected. Linux only.
+    #[cfg(feature = "sandbox")]
+    #[structopt(long = "sandbox")]
+    pub sandbox: bool,
+
+    /// Write build timeline
+    ///
+    /// Records processing of rules in Chrome Trace Event format which can be loaded into Perfetto or `chrome://tracing`.
+    #[structopt(name = "file", long = "trace")]
+    pub trace: Option<PathBuf>,
+
     /// Watch mode
     ///
     /// In this mode goals will be updated when updating dependencies.
@@ -169,6 +277,39 @@ impl Args {
         self.jobs.unwrap_or_else(|| num_cpus::get())
     }
 
+    pub fn get_failures(&self) -> usize {
+        match self.keep_going {
+            None => 1,
+            Some(None) => 0,
+            Some(Some(failures)) => failures,
+        }
+    }
+
+    pub fn get_process_opts(&self) -> gear::ProcessOpts {
+        gear::ProcessOpts {
+            jobs: self.get_jobs(),
+            failures: self.get_failures(),
+            dry_run: self.dry_run,
+            explain: self.explain,
+            trace: self.trace.clone(),
+            #[cfg(feature = "sandbox")]
+            sandbox: self.sandbox,
+            #[cfg(not(feature = "sandbox"))]
+            sandbox: false,
+        }
+    }
+
+    pub fn get_cache(&self) -> Option<(PathBuf, u64)> {
+        self.cache
+            .clone()
+            .map(|dir| (dir, self.cache_size.unwrap_or(CACHE_SIZE) * 1024 * 1024))
+    }
+
+    pub fn get_history(&self) -> Option<usize> {
+        self.history
+            .map(|builds| builds.unwrap_or(gear::HISTORY_BUILDS))
+    }
+
     pub fn get_print(&self) -> Option<Print> {
         self.print_db.map(|print| print.unwrap_or_default())
     }
@@ -207,9 +348,10 @@ impl Args {
 pub enum Print {
     Goals,
     Graph,
+    Json,
 }
 
-const PRINT_VALUES: &[&str] = &["plain", "dot"];
+const PRINT_VALUES: &[&str] = &["plain", "dot", "json"];
 
 impl FromStr for Print {
     type Err = String;
@@ -217,6 +359,7 @@ impl FromStr for Print {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(match s {
             "graph" | "graphviz" | "dot" => Self::Graph,
+            "json" => Self::Json,
             _ => Self::Goals,
         })
     }
@@ -228,6 +371,25 @@ impl Default for Print {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum QueryOutput {
+    Names,
+    Json,
+}
+
+const QUERY_OUTPUT_VALUES: &[&str] = &["names", "json"];
+
+impl FromStr for QueryOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "json" => Self::Json,
+            _ => Self::Names,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Input {
     Pair(String, String),
@@ -4,9 +4,13 @@ use super::{
 };
 use crate::{
     qjs,
-    system::{check_access, exec_out, which_any, write_file, AccessMode, Path, PathBuf},
-    Actual, Artifact, ArtifactStore, BoxedFuture, DataHasher, Diagnostics, Directory, Input, Mut,
-    Output, Ref, Result, Rule, RuleApi, Set, WeakArtifact,
+    system::{
+        check_access, exec_out, remove_file, rename, which_any, write_file, AccessMode, Path,
+        PathBuf, Sandbox,
+    },
+    Actual, Artifact, ArtifactStore, BoxedFuture, DataHasher, Diagnostics, Directory, Input,
+    Invocation, Mut, Output, Ref, Result, Rule, RuleApi, RulePolicy, RuleSignature, Set,
+    WeakArtifact,
 };
 use futures::future::{join_all, FutureExt};
 use std::iter::once;
@@ -34,6 +38,30 @@ macro_rules! log_out {
     };
 }
 
+/// Get the temporary name of output
+///
+/// The native rules writes outputs to temporary files which will be renamed on success.
+/// The stale temporary file which is left by interrupted build will be removed.
+async fn temp_name(name: &str) -> Result<String> {
+    let tmp_name = format!("{}.tmp", name);
+    if Path::new(&tmp_name).is_file().await {
+        remove_file(&tmp_name).await?;
+    }
+    Ok(tmp_name)
+}
+
+/// Move temporary output to its place on success or remove it otherwise
+async fn commit_output(tmp_name: &str, name: &str, success: bool) -> Result<()> {
+    if Path::new(tmp_name).is_file().await {
+        if success {
+            rename(tmp_name, name).await?;
+        } else {
+            remove_file(tmp_name).await?;
+        }
+    }
+    Ok(())
+}
+
 #[derive(Hash)]
 struct PropsInternal {
     /// C compiler path
@@ -267,6 +295,41 @@ impl CompilerConfig {
         self.0.opts.strip.fmt_args(&mut out);
         out
     }
+
+    /// Get the directories of toolchain which should be visible in sandbox
+    ///
+    /// Includes installation prefixes of tools, sysroot and search directories from options.
+    pub fn toolchain_dirs(&self) -> Vec<String> {
+        let props = &self.0.props;
+        let opts = &self.0.opts;
+        let mut dirs = Set::default();
+        for tool in once(&props.cc).chain(props.dc.iter()).chain(
+            [
+                &props.ar,
+                &props.nm,
+                &props.size,
+                &props.strip,
+                &props.objcopy,
+                &props.objdump,
+                &props.readelf,
+            ]
+            .iter()
+            .copied(),
+        ) {
+            dirs.extend(Sandbox::tool_dirs(tool));
+        }
+        dirs.extend(opts.base.sysroot.iter().cloned());
+        for search_dirs in &[
+            &opts.cc.dirs,
+            &opts.c.dirs,
+            &opts.cxx.dirs,
+            &opts.d.dirs,
+            &opts.link.dirs,
+        ] {
+            dirs.extend(search_dirs.iter().cloned());
+        }
+        dirs.into_iter().collect()
+    }
 }
 
 #[derive(Clone)]
@@ -317,21 +380,37 @@ impl Internal {
     }
 }
 
-#[derive(Debug, Clone, qjs::FromJs, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct CompileOptions {
     pub input: Option<CInputKind>,
     pub output: COutputKind,
+    pub policy: RulePolicy,
+}
+
+impl<'js> qjs::FromJs<'js> for CompileOptions {
+    fn from_js(_ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        let obj: qjs::Object = val.get()?;
+        let input = obj.get("input")?;
+        let output = obj.get("output")?;
+        let policy = val.get()?;
+
+        Ok(Self {
+            input,
+            output,
+            policy,
+        })
+    }
 }
 
 pub(self) struct CompileInternal {
     cfg: CompilerConfig,
-    store: ArtifactStore,
     in_kind: CInputKind,
     out_kind: COutputKind,
     src: Artifact<Input, Actual>,
     dep: PathBuf,
     incs: Mut<Set<Artifact<Input, Actual>>>,
     dst: WeakArtifact<Output, Actual>,
+    policy: RulePolicy,
 }
 
 impl Drop for CompileInternal {
@@ -340,12 +419,117 @@ impl Drop for CompileInternal {
     }
 }
 
+impl CompileInternal {
+    /// Get the command which writes output to `tmp_name`
+    ///
+    /// The target of generated dependencies is always the `dst_name`.
+    fn command(&self, dst_name: &str, tmp_name: &str) -> (&String, Vec<String>) {
+        let deps_name = self.dep.display().to_string();
+        let src = &self.src;
+
+        if self.in_kind == CInputKind::D {
+            let mut args = self.cfg.d_opts();
+
+            match DCompilerKind::from(self.cfg.0.props.kind) {
+                DCompilerKind::Gdc => {
+                    args.push(
+                        match self.out_kind {
+                            COutputKind::Asm => "-S",
+                            COutputKind::Obj => "-c",
+                            _ => unreachable!(),
+                        }
+                        .into(),
+                    );
+                    args.push("-MMD".into());
+                    args.push("-MF".into());
+                    args.push(deps_name);
+                    args.push("-MT".into());
+                    args.push(dst_name.into());
+                    args.push("-o".into());
+                    args.push(tmp_name.into());
+                    args.push(src.name().clone());
+                }
+                DCompilerKind::Ldc => {
+                    args.push("--verror-style=gnu".into());
+                    args.push(format!("--mtriple={}", self.cfg.0.props.target));
+                    args.push(format!(
+                        "--output-{}",
+                        match self.out_kind {
+                            COutputKind::Asm => "s",
+                            COutputKind::Obj => "o",
+                            COutputKind::Ir => "ll",
+                            COutputKind::Bc => "bc",
+                            _ => unreachable!(),
+                        }
+                    ));
+                    args.push(format!("--deps={}", deps_name));
+                    args.push("--op".into());
+                    args.push(format!("--of={}", tmp_name));
+                    args.push(src.name().clone());
+                }
+            }
+
+            (self.cfg.0.props.dc.as_ref().unwrap(), args)
+        } else {
+            fn with_lang(lang: &str, mut args: Vec<String>) -> Vec<String> {
+                args.push(format!("-x{}", lang));
+                args
+            }
+
+            let mut args = match self.in_kind {
+                CInputKind::C => with_lang("c", self.cfg.c_opts()),
+                CInputKind::Asm => with_lang("assembler-with-cpp", self.cfg.c_opts()),
+                CInputKind::Cxx => with_lang("c++", self.cfg.cxx_opts()),
+                _ => unreachable!(),
+            };
+
+            if matches!(self.cfg.0.props.kind, CompilerKind::Llvm) {
+                args.push(format!("--target={}", self.cfg.0.props.target));
+
+                if matches!(self.out_kind, COutputKind::Ir | COutputKind::Bc) {
+                    args.push("--emit-llvm".into());
+                }
+            }
+
+            args.push(
+                match self.out_kind {
+                    COutputKind::Cpp => "-E",
+                    COutputKind::Asm | COutputKind::Ir => "-S",
+                    COutputKind::Obj | COutputKind::Bc => "-c",
+                }
+                .into(),
+            );
+
+            args.push("-MMD".into());
+            args.push("-MF".into());
+            args.push(deps_name);
+            args.push("-MT".into());
+            args.push(dst_name.into());
+            args.push("-o".into());
+            args.push(tmp_name.into());
+            args.push(src.name().clone());
+
+            (&self.cfg.0.props.cc, args)
+        }
+    }
+
+    fn dep_kind(&self) -> DepKind {
+        if self.in_kind == CInputKind::D
+            && matches!(
+                DCompilerKind::from(self.cfg.0.props.kind),
+                DCompilerKind::Ldc
+            )
+        {
+            DepKind::D
+        } else {
+            DepKind::default()
+        }
+    }
+}
+
 impl RuleApi for CompileInternal {
     fn inputs(&self) -> Vec<Artifact<Input>> {
-        once(&self.src)
-            .chain(self.incs.read().iter())
-            .map(|input| input.clone().into_kind_any())
-            .collect()
+        vec![self.src.clone().into_kind_any()]
     }
 
     fn outputs(&self) -> Vec<Artifact<Output>> {
@@ -356,111 +540,66 @@ impl RuleApi for CompileInternal {
             .collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
-        async move {
-            log::debug!("Compile::invoke");
-            Ok(if let Some(dst) = self.dst.try_ref() {
-                let deps_name = self.dep.display().to_string();
-                let src = &self.src;
-                let mut dep_kind = DepKind::default();
-
-                let (cmd, args) = if self.in_kind == CInputKind::D {
-                    let mut args = self.cfg.d_opts();
-
-                    match DCompilerKind::from(self.cfg.0.props.kind) {
-                        DCompilerKind::Gdc => {
-                            args.push(
-                                match self.out_kind {
-                                    COutputKind::Asm => "-S",
-                                    COutputKind::Obj => "-c",
-                                    _ => unreachable!(),
-                                }
-                                .into(),
-                            );
-                            args.push("-MMD".into());
-                            args.push("-MF".into());
-                            args.push(deps_name);
-                            args.push("-o".into());
-                            args.push(dst.name().clone());
-                            args.push(src.name().clone());
-                        }
-                        DCompilerKind::Ldc => {
-                            args.push("--verror-style=gnu".into());
-                            args.push(format!("--mtriple={}", self.cfg.0.props.target));
-                            args.push(format!(
-                                "--output-{}",
-                                match self.out_kind {
-                                    COutputKind::Asm => "s",
-                                    COutputKind::Obj => "o",
-                                    COutputKind::Ir => "ll",
-                                    COutputKind::Bc => "bc",
-                                    _ => unreachable!(),
-                                }
-                            ));
-                            args.push(format!("--deps={}", deps_name));
-                            dep_kind = DepKind::D;
-                            args.push("--op".into());
-                            args.push(format!("--of={}", dst.name()));
-                            args.push(src.name().clone());
-                        }
-                    }
+    fn signature(&self) -> RuleSignature {
+        self.dst
+            .try_ref()
+            .map(|dst| {
+                let (cmd, args) = self.command(dst.name(), dst.name());
+                RuleSignature::exec(cmd, args)
+            })
+            .unwrap_or_default()
+    }
 
-                    (self.cfg.0.props.dc.as_ref().unwrap(), args)
-                } else {
-                    fn with_lang(lang: &str, mut args: Vec<String>) -> Vec<String> {
-                        args.push(format!("-x{}", lang));
-                        args
-                    }
-
-                    let mut args = match self.in_kind {
-                        CInputKind::C => with_lang("c", self.cfg.c_opts()),
-                        CInputKind::Asm => with_lang("assembler-with-cpp", self.cfg.c_opts()),
-                        CInputKind::Cxx => with_lang("c++", self.cfg.cxx_opts()),
-                        _ => unreachable!(),
-                    };
-
-                    if matches!(self.cfg.0.props.kind, CompilerKind::Llvm) {
-                        args.push(format!("--target={}", self.cfg.0.props.target));
-
-                        if matches!(self.out_kind, COutputKind::Ir | COutputKind::Bc) {
-                            args.push("--emit-llvm".into());
-                        }
-                    }
+    fn side_outputs(&self) -> Vec<String> {
+        vec![self.dep.display().to_string()]
+    }
 
-                    args.push(
-                        match self.out_kind {
-                            COutputKind::Cpp => "-E",
-                            COutputKind::Asm | COutputKind::Ir => "-S",
-                            COutputKind::Obj | COutputKind::Bc => "-c",
-                        }
-                        .into(),
-                    );
+    fn depfile(&self) -> Option<(String, DepKind)> {
+        Some((self.dep.display().to_string(), self.dep_kind()))
+    }
 
-                    args.push("-MMD".into());
-                    args.push("-MF".into());
-                    args.push(deps_name);
-                    args.push("-o".into());
-                    args.push(dst.name().clone());
-                    args.push(src.name().clone());
+    fn discovered_inputs(&self) -> Option<Vec<Artifact<Input>>> {
+        Some(
+            self.incs
+                .read()
+                .iter()
+                .map(|input| input.clone().into_kind_any())
+                .collect(),
+        )
+    }
 
-                    (&self.cfg.0.props.cc, args)
-                };
+    fn set_discovered_inputs(&self, inputs: Set<Artifact<Input>>) {
+        *self.incs.write() = inputs
+            .into_iter()
+            .filter_map(|input| input.into_kind().ok())
+            .collect();
+    }
 
-                let res = exec_out(cmd, &args).await?;
-                log_out!(res);
+    fn cacheable(&self) -> bool {
+        true
+    }
 
-                let dep_path = &self.dep;
-                if dep_path.is_file().await {
-                    let src_name = self.src.name();
-                    // reload generated deps
-                    let incs = self
-                        .store
-                        .read_deps(dep_path, dep_kind, |src| src != src_name)
-                        .await?;
-                    *self.incs.write() = incs;
-                }
+    fn policy(&self) -> RulePolicy {
+        self.policy
+    }
 
-                res.err.parse()?
+    fn sandbox_paths(&self) -> Vec<String> {
+        self.cfg.toolchain_dirs()
+    }
+
+    fn invoke(self: Ref<Self>, invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+        async move {
+            log::debug!("Compile::invoke");
+            Ok(if let Some(dst) = self.dst.try_ref() {
+                let tmp_name = temp_name(dst.name()).await?;
+                let (cmd, args) = self.command(dst.name(), &tmp_name);
+
+                let res = invocation.exec_out(cmd, &args).await?;
+                log_out!(res);
+                let diagnostics: Diagnostics = res.err.parse()?;
+                commit_output(&tmp_name, dst.name(), !diagnostics.is_failed()).await?;
+                // generated deps will be reloaded by rule processor
+                diagnostics
             } else {
                 Default::default()
             })
@@ -479,6 +618,8 @@ pub struct LinkOutput {
 pub struct LinkOptions {
     output: FileKind,
     script: Option<Artifact<Input, Actual>>,
+    pool: Option<String>,
+    policy: RulePolicy,
 }
 
 impl<'js> qjs::FromJs<'js> for LinkOptions {
@@ -490,8 +631,15 @@ impl<'js> qjs::FromJs<'js> for LinkOptions {
             Default::default()
         };
         let script = obj.get("script")?;
+        let pool = obj.get("pool")?;
+        let policy = val.get()?;
 
-        Ok(Self { output, script })
+        Ok(Self {
+            output,
+            script,
+            pool,
+            policy,
+        })
     }
 }
 
@@ -500,6 +648,8 @@ pub(self) struct LinkInternal {
     out_kind: FileKind,
     objs: Set<Artifact<Input, Actual>>,
     script: Option<Artifact<Input, Actual>>,
+    pool: Option<String>,
+    policy: RulePolicy,
     out: WeakArtifact<Output, Actual>,
     map: WeakArtifact<Output, Actual>,
 }
@@ -510,6 +660,38 @@ impl Drop for LinkInternal {
     }
 }
 
+impl LinkInternal {
+    fn command(&self, out_name: &str) -> (&String, Vec<String>) {
+        let (cmd, mut args) = if matches!(self.out_kind, FileKind::Static { .. }) {
+            (&self.cfg.0.props.ar, vec!["cr".into(), out_name.into()])
+        } else {
+            let mut args = self.cfg.link_opts();
+
+            args.push("-o".into());
+            args.push(out_name.into());
+
+            if matches!(self.out_kind, FileKind::Dynamic { .. }) {
+                args.push("-shared".into());
+            }
+
+            if let Some(script) = &self.script {
+                args.push("-T".into());
+                args.push(script.name().clone());
+            }
+
+            if let Some(map) = self.map.try_ref() {
+                args.push(format!("-Wl,-Map,{}", map.name()));
+            }
+
+            (&self.cfg.0.props.cc, args)
+        };
+
+        args.extend(self.objs.iter().map(|obj| obj.name().clone()));
+
+        (cmd, args)
+    }
+}
+
 impl RuleApi for LinkInternal {
     fn inputs(&self) -> Vec<Artifact<Input>> {
         self.script
@@ -527,39 +709,52 @@ impl RuleApi for LinkInternal {
             .collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
-        async move {
-            log::debug!("Link::invoke");
-            Ok(if let Some(out) = self.out.try_ref() {
-                let (cmd, mut args) = if matches!(self.out_kind, FileKind::Static { .. }) {
-                    (&self.cfg.0.props.ar, vec!["cr".into(), out.name().clone()])
-                } else {
-                    let mut args = self.cfg.link_opts();
+    fn signature(&self) -> RuleSignature {
+        self.out
+            .try_ref()
+            .map(|out| {
+                let (cmd, args) = self.command(out.name());
+                RuleSignature::exec(cmd, args)
+            })
+            .unwrap_or_default()
+    }
 
-                    args.push("-o".into());
-                    args.push(out.name().clone());
+    fn pool(&self) -> Option<String> {
+        self.pool.clone()
+    }
 
-                    if matches!(self.out_kind, FileKind::Dynamic { .. }) {
-                        args.push("-shared".into());
-                    }
+    fn side_outputs(&self) -> Vec<String> {
+        self.map
+            .try_ref()
+            .map(|map| map.name().clone())
+            .into_iter()
+            .collect()
+    }
 
-                    if let Some(script) = &self.script {
-                        args.push("-T".into());
-                        args.push(script.name().clone());
-                    }
+    fn cacheable(&self) -> bool {
+        true
+    }
 
-                    if let Some(map) = self.map.try_ref() {
-                        args.push(format!("-Wl,-Map,{}", map.name()));
-                    }
+    fn policy(&self) -> RulePolicy {
+        self.policy
+    }
 
-                    (&self.cfg.0.props.cc, args)
-                };
+    fn sandbox_paths(&self) -> Vec<String> {
+        self.cfg.toolchain_dirs()
+    }
 
-                args.extend(self.objs.iter().map(|obj| obj.name().clone()));
+    fn invoke(self: Ref<Self>, invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+        async move {
+            log::debug!("Link::invoke");
+            Ok(if let Some(out) = self.out.try_ref() {
+                let tmp_name = temp_name(out.name()).await?;
+                let (cmd, args) = self.command(&tmp_name);
 
-                let res = exec_out(cmd, &args).await?;
+                let res = invocation.exec_out(cmd, &args).await?;
                 log_out!(res);
-                res.err.parse()?
+                let diagnostics: Diagnostics = res.err.parse()?;
+                commit_output(&tmp_name, out.name(), !diagnostics.is_failed()).await?;
+                diagnostics
             } else {
                 Default::default()
             })
@@ -587,6 +782,32 @@ impl Drop for StripInternal {
     }
 }
 
+impl StripInternal {
+    /// Get the command which strips output
+    ///
+    /// The stripped output will be written to `dst_name` when it is set or in place otherwise.
+    fn command(
+        &self,
+        out: &Artifact<Output, Actual>,
+        dst_name: Option<&str>,
+    ) -> (&String, Vec<String>) {
+        let mut args = self.cfg.strip_opts();
+
+        if let Some(dst_name) = dst_name {
+            args.push("-o".into());
+            args.push(dst_name.into());
+        }
+
+        args.push(out.name().clone());
+
+        (&self.cfg.0.props.strip, args)
+    }
+
+    fn strip_out(&self) -> Option<Artifact<Output, Actual>> {
+        self.strip_out.as_ref().and_then(|out| out.try_ref())
+    }
+}
+
 impl RuleApi for StripInternal {
     fn inputs(&self) -> Vec<Artifact<Input>> {
         vec![self.obj.clone().into_kind_any()]
@@ -605,23 +826,37 @@ impl RuleApi for StripInternal {
             .collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
+    fn signature(&self) -> RuleSignature {
+        self.out
+            .try_ref()
+            .map(|out| {
+                let strip_out = self.strip_out();
+                let (cmd, args) =
+                    self.command(&out, strip_out.as_ref().map(|out| out.name().as_str()));
+                RuleSignature::exec(cmd, args)
+            })
+            .unwrap_or_default()
+    }
+
+    fn cacheable(&self) -> bool {
+        true
+    }
+
+    fn sandbox_paths(&self) -> Vec<String> {
+        self.cfg.toolchain_dirs()
+    }
+
+    fn invoke(self: Ref<Self>, invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
         async move {
             log::debug!("Strip::invoke");
             if let Some(out) = self.out.try_ref() {
-                let mut args = self.cfg.strip_opts();
+                let dst = self.strip_out().unwrap_or_else(|| out.clone());
+                let tmp_name = temp_name(dst.name()).await?;
+                let (cmd, args) = self.command(&out, Some(&tmp_name));
 
-                if let Some(strip_out) = self.strip_out.as_ref() {
-                    if let Some(strip_out) = strip_out.try_ref() {
-                        args.push("-o".into());
-                        args.push(strip_out.name().clone());
-                    }
-                }
-
-                args.push(out.name().clone());
-
-                let res = exec_out(&self.cfg.0.props.strip, &args).await?;
+                let res = invocation.exec_out(cmd, &args).await?;
                 log_out!(res);
+                commit_output(&tmp_name, dst.name(), res.is_success()).await?;
                 res.success()?;
             }
             Ok(Default::default())
@@ -640,6 +875,7 @@ impl CompilerConfig {
         let opts = opts.unwrap_or_default();
 
         let out_kind = opts.output;
+        let policy = opts.policy;
         let src_name = src.name().clone();
 
         let in_kind = if let Some(kind) = opts.input {
@@ -703,16 +939,16 @@ impl CompilerConfig {
 
         let rule = Ref::new(CompileInternal {
             cfg: self.clone(),
-            store: store.clone(),
             in_kind,
             out_kind,
             src,
             dep: dep_path,
             incs: Mut::new(incs),
             dst: dst.weak(),
+            policy,
         });
 
-        dst.set_rule(Rule::from_api(rule));
+        dst.set_rule(Rule::from_api(rule))?;
 
         Ok(dst.into())
     }
@@ -728,6 +964,8 @@ impl CompilerConfig {
 
         let script = opts.script;
         let out_kind = opts.output;
+        let pool = opts.pool;
+        let policy = opts.policy;
 
         let out_name = out_kind.file_name(&self.0.props.platform, out_name);
         let out = out_dir.output(&out_name).await?;
@@ -742,14 +980,16 @@ impl CompilerConfig {
             out_kind,
             objs,
             script,
+            pool,
+            policy,
             out: out.weak(),
             map: map.weak(),
         });
 
         let rule = Rule::from_api(rule);
 
-        out.set_rule(rule.clone());
-        map.set_rule(rule);
+        out.set_rule(rule.clone())?;
+        map.set_rule(rule)?;
 
         Ok(LinkOutput {
             out: out.into(),
@@ -788,10 +1028,10 @@ impl CompilerConfig {
 
         let rule = Rule::from_api(rule);
         if let Some(strip_out) = &strip_out {
-            out.set_rule(rule.clone());
-            strip_out.set_rule(rule);
+            out.set_rule(rule.clone())?;
+            strip_out.set_rule(rule)?;
         } else {
-            out.set_rule(rule);
+            out.set_rule(rule)?;
         }
 
         Ok(StripOutput {
@@ -856,15 +1096,19 @@ impl RuleApi for LdScriptInternal {
             .collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
+    fn signature(&self) -> RuleSignature {
+        RuleSignature {
+            args: vec![self.content()],
+            ..Default::default()
+        }
+    }
+
+    fn invoke(self: Ref<Self>, _invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
         async move {
             log::debug!("LdScript::invoke");
 
             if let Some(out) = self.out.try_ref() {
-                let mut data = LdScript::default();
-                data.includes = self.incs.iter().map(|inc| inc.name().into()).collect();
-                let content = format!("{}{}", self.data, data);
-                write_file(out.name(), content).await?;
+                write_file(out.name(), self.content()).await?;
             }
 
             Ok(Default::default())
@@ -874,6 +1118,12 @@ impl RuleApi for LdScriptInternal {
 }
 
 impl LdScriptInternal {
+    fn content(&self) -> String {
+        let mut data = LdScript::default();
+        data.includes = self.incs.iter().map(|inc| inc.name().into()).collect();
+        format!("{}{}", self.data, data)
+    }
+
     pub async fn create(
         outdir: Directory,
         name: impl AsRef<str>,
@@ -891,7 +1141,7 @@ impl LdScriptInternal {
             incs,
         });
 
-        out.set_rule(Rule::from_api(rule));
+        out.set_rule(Rule::from_api(rule))?;
 
         Ok(out.into())
     }
@@ -1099,3 +1349,29 @@ mod js {
         LdScriptInternal::create(outdir, name, opts, incs.0.unwrap_or_default()).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn temp_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = dir.path().join("libfoo.a").display().to_string();
+
+        // stale temporary file is removed before invoking
+        write_file(format!("{}.tmp", name), "stale").await.unwrap();
+        let tmp_name = temp_name(&name).await.unwrap();
+        assert!(!Path::new(&tmp_name).exists().await);
+
+        write_file(&tmp_name, "partial").await.unwrap();
+        commit_output(&tmp_name, &name, false).await.unwrap();
+        assert!(!Path::new(&tmp_name).exists().await);
+        assert!(!Path::new(&name).exists().await);
+
+        write_file(&tmp_name, "complete").await.unwrap();
+        commit_output(&tmp_name, &name, true).await.unwrap();
+        assert!(!Path::new(&tmp_name).exists().await);
+        assert_eq!(crate::system::read_file(&name).await.unwrap(), b"complete");
+    }
+}
@@ -5,11 +5,15 @@ mod ld_script;
 mod size_parser;
 
 use crate::{
+    qjs,
     system::{read_file, Path},
     Actual, Artifact, ArtifactStore, Input, Result, Set,
 };
 use futures::future::join_all;
-use std::str::from_utf8;
+use std::{
+    result::Result as StdResult,
+    str::{from_utf8, FromStr},
+};
 
 pub use d_deps_parser::DDepsInfo;
 pub use deps_parser::DepsInfo;
@@ -28,6 +32,41 @@ impl Default for DepKind {
     }
 }
 
+impl FromStr for DepKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "make" => Ok(Self::Make),
+            "d" => Ok(Self::D),
+            _ => Err(()),
+        }
+    }
+}
+
+impl DepKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Make => "make",
+            Self::D => "d",
+        }
+    }
+}
+
+impl<'js> qjs::FromJs<'js> for DepKind {
+    fn from_js(_ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        let val: String = val.get()?;
+        val.parse()
+            .map_err(|_| qjs::Error::new_from_js("string", "DepKind"))
+    }
+}
+
+impl<'js> qjs::IntoJs<'js> for DepKind {
+    fn into_js(self, ctx: qjs::Ctx<'js>) -> qjs::Result<qjs::Value<'js>> {
+        self.as_str().into_js(ctx)
+    }
+}
+
 impl ArtifactStore {
     pub async fn read_deps(
         &self,
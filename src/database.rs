@@ -0,0 +1,241 @@
+use crate::{
+    system::{create_dir_all, read_file, write_file, Path, PathBuf},
+    Duration, Map, Mut, Ref, Result, Time,
+};
+use derive_deref::Deref;
+use serde::{Deserialize, Serialize};
+
+/// The name of build database file in destination directory
+pub const DATABASE_FILE: &str = ".gear_db";
+
+/// The version of build database format
+const DATABASE_VERSION: u32 = 3;
+
+/// The recorded state of rule input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputEntry {
+    /// The time of input when it was used
+    pub time: Time,
+    /// The digest of input contents (empty for phony inputs)
+    pub digest: String,
+}
+
+/// The recorded state of rule output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputEntry {
+    /// The digest of rule signature
+    pub signature: String,
+    /// The time of output when it was produced
+    pub time: Time,
+    /// The digest of output contents (empty for non-files)
+    pub digest: String,
+    /// The duration of rule processing
+    pub duration: Duration,
+    /// The states of inputs which was used to produce output
+    pub inputs: Map<String, InputEntry>,
+    /// The files which is generated by rule in addition to outputs
+    #[serde(default)]
+    pub side: Vec<String>,
+    /// The inputs which was discovered by rule while processing
+    #[serde(default)]
+    pub discovered: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Data {
+    version: u32,
+    outputs: Map<String, OutputEntry>,
+}
+
+impl Default for Data {
+    fn default() -> Self {
+        Self {
+            version: DATABASE_VERSION,
+            outputs: Default::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Internal {
+    path: Mut<Option<PathBuf>>,
+    data: Mut<Data>,
+    changed: Mut<bool>,
+}
+
+/// The persistent build database
+///
+/// Keeps the states of inputs and signatures of rules which was used to produce outputs.
+#[derive(Default, Clone, Deref)]
+pub struct Database(Ref<Internal>);
+
+impl Database {
+    /// Open database in specified directory
+    ///
+    /// The missing or incompatible database will be silently replaced by empty one.
+    pub async fn open(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let path = dir.as_ref().join(DATABASE_FILE);
+        let data = if path.is_file().await {
+            log::debug!("Load build database `{}`", path.display());
+            let raw = read_file(&path).await?;
+            match serde_json::from_slice::<Data>(&raw) {
+                Ok(data) if data.version == DATABASE_VERSION => data,
+                Ok(_) => {
+                    log::warn!(
+                        "Build database `{}` has incompatible version. Ignored.",
+                        path.display()
+                    );
+                    Default::default()
+                }
+                Err(error) => {
+                    log::warn!(
+                        "Build database `{}` is broken due to: {}. Ignored.",
+                        path.display(),
+                        error
+                    );
+                    Default::default()
+                }
+            }
+        } else {
+            Default::default()
+        };
+        *self.0.data.write() = data;
+        *self.0.path.write() = Some(path);
+        *self.0.changed.write() = false;
+        Ok(())
+    }
+
+    /// Save database when it was changed
+    pub async fn save(&self) -> Result<()> {
+        let path = if let Some(path) = &*self.0.path.read() {
+            path.clone()
+        } else {
+            return Ok(());
+        };
+        if !*self.0.changed.read() {
+            return Ok(());
+        }
+        log::debug!("Save build database `{}`", path.display());
+        let raw = serde_json::to_vec(&*self.0.data.read())?;
+        if let Some(dir) = path.parent() {
+            if !dir.is_dir().await {
+                create_dir_all(dir).await?;
+            }
+        }
+        write_file(&path, raw).await?;
+        *self.0.changed.write() = false;
+        Ok(())
+    }
+
+    /// Get recorded state of output
+    pub fn output(&self, name: impl AsRef<str>) -> Option<OutputEntry> {
+        self.0.data.read().outputs.get(name.as_ref()).cloned()
+    }
+
+    /// Get recorded states of all outputs
+    pub fn outputs(&self) -> Vec<(String, OutputEntry)> {
+        self.0
+            .data
+            .read()
+            .outputs
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Get recorded duration of rule processing which produces output
+    pub fn duration(&self, name: impl AsRef<str>) -> Option<Duration> {
+        self.0
+            .data
+            .read()
+            .outputs
+            .get(name.as_ref())
+            .map(|entry| entry.duration)
+    }
+
+    /// Record state of output
+    pub fn set_output(&self, name: impl Into<String>, entry: OutputEntry) {
+        self.0.data.write().outputs.insert(name.into(), entry);
+        *self.0.changed.write() = true;
+    }
+
+    /// Forget state of output
+    pub fn remove_output(&self, name: impl AsRef<str>) {
+        if self.0.data.write().outputs.remove(name.as_ref()).is_some() {
+            *self.0.changed.write() = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(signature: &str, digest: &str) -> OutputEntry {
+        let mut inputs = Map::default();
+        inputs.insert(
+            "main.c".into(),
+            InputEntry {
+                time: Time::UNIX_EPOCH + Duration::from_secs(1),
+                digest: "c-digest".into(),
+            },
+        );
+        OutputEntry {
+            signature: signature.into(),
+            time: Time::UNIX_EPOCH + Duration::from_secs(2),
+            digest: digest.into(),
+            duration: Duration::from_millis(100),
+            inputs,
+            side: vec!["main.o.dep".into()],
+            discovered: vec!["main.h".into()],
+        }
+    }
+
+    #[async_std::test]
+    async fn persistence() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert!(db.output("main.o").is_none());
+        db.set_output("main.o", entry("sig", "o-digest"));
+        db.save().await.unwrap();
+
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert_eq!(db.output("main.o"), Some(entry("sig", "o-digest")));
+        assert_eq!(db.duration("main.o"), Some(Duration::from_millis(100)));
+
+        db.set_output("main.o", entry("new-sig", "new-digest"));
+        db.remove_output("main");
+        db.save().await.unwrap();
+
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert_eq!(db.output("main.o"), Some(entry("new-sig", "new-digest")));
+        db.remove_output("main.o");
+        db.save().await.unwrap();
+
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert!(db.outputs().is_empty());
+    }
+
+    #[async_std::test]
+    async fn incompatible() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DATABASE_FILE).display().to_string();
+
+        write_file(&path, r#"{"version":0,"outputs":{}}"#)
+            .await
+            .unwrap();
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert!(db.outputs().is_empty());
+
+        write_file(&path, "broken").await.unwrap();
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert!(db.outputs().is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+/*!
+Machine-readable dump of goals, variables and rules graph
+ */
+
+use crate::{
+    Artifact, ArtifactStore, Rule, RuleId, RuleState, Scope, Value, ValueDef, ValueSource, Variable,
+};
+use serde::Serialize;
+
+/// The version of dump format
+///
+/// It should be increased on incompatible changes of schema.
+const DUMP_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct VariableEntry {
+    name: String,
+    description: String,
+    definition: ValueDef,
+    value: Value,
+    default: Value,
+    source: ValueSource,
+}
+
+impl From<&Variable> for VariableEntry {
+    fn from(var: &Variable) -> Self {
+        Self {
+            name: var.name().into(),
+            description: var.description().into(),
+            definition: var.definition().clone(),
+            value: var.value(),
+            default: var.default().clone(),
+            source: var.source(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GoalEntry {
+    name: String,
+    description: String,
+    inputs: Vec<String>,
+}
+
+impl<U, K> From<&Artifact<U, K>> for GoalEntry {
+    fn from(goal: &Artifact<U, K>) -> Self {
+        Self {
+            name: goal.name().clone(),
+            description: goal.description().clone(),
+            inputs: goal.inputs().map(|input| input.name().clone()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ScopeEntry {
+    name: String,
+    description: String,
+    variables: Vec<VariableEntry>,
+    goals: Vec<GoalEntry>,
+    scopes: Vec<ScopeEntry>,
+}
+
+impl ScopeEntry {
+    fn new(scope: &Scope, matcher: &impl Fn(&str) -> bool) -> Self {
+        Self {
+            name: scope.name().clone(),
+            description: scope.description().clone(),
+            variables: scope
+                .vars()
+                .iter()
+                .filter(|var| matcher(var.name()))
+                .map(VariableEntry::from)
+                .collect(),
+            goals: scope
+                .goals()
+                .iter()
+                .filter(|goal| matcher(goal.name()))
+                .map(GoalEntry::from)
+                .collect(),
+            scopes: scope
+                .scopes()
+                .iter()
+                .filter(|scope| matcher(scope.name()))
+                .map(|scope| Self::new(scope, matcher))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RuleEntry {
+    id: RuleId,
+    state: RuleState,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+}
+
+impl From<&Rule> for RuleEntry {
+    fn from(rule: &Rule) -> Self {
+        Self {
+            id: rule.id(),
+            state: rule.state(),
+            inputs: rule
+                .deps()
+                .into_iter()
+                .map(|artifact| artifact.name().clone())
+                .collect(),
+            outputs: rule
+                .outputs()
+                .into_iter()
+                .map(|artifact| artifact.name().clone())
+                .collect(),
+        }
+    }
+}
+
+/// The dump of scopes with its variables and goals and rules which is needed to build goals
+#[derive(Serialize)]
+pub struct Dump {
+    version: u32,
+    scope: ScopeEntry,
+    rules: Vec<RuleEntry>,
+}
+
+impl Dump {
+    /// Create dump of scope using goals which names is matched
+    pub fn new(scope: &Scope, matcher: &impl Fn(&str) -> bool) -> Self {
+        let store: &ArtifactStore = scope.as_ref();
+        let rules = store.goal_rules(
+            store
+                .phony
+                .read()
+                .iter()
+                .filter(|goal| matcher(goal.name())),
+        );
+
+        Self {
+            version: DUMP_VERSION,
+            scope: ScopeEntry::new(scope, matcher),
+            rules: rules.iter().map(RuleEntry::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Actual, Input, NoRule, Output, Store, ValueStore, VariableStore};
+    use serde_json::json;
+    use std::iter::once;
+
+    #[test]
+    fn schema() {
+        let mut values = ValueStore::new("config.json").unwrap();
+        values.set("lib.debug", Some(&Value::Bool(true)));
+        let args = once(("cc".to_string(), "\"clang\"".to_string()));
+        let variables = VariableStore::new(values, args);
+        let root = Scope::new_root(Store::new(variables, ArtifactStore::default()));
+        let lib = root.new_scope("lib", "Library").unwrap();
+
+        let _cc = root
+            .new_var(
+                "cc",
+                "C compiler",
+                Some(ValueDef::Any),
+                Some(Value::String("gcc".into())),
+            )
+            .unwrap();
+        let _debug = lib
+            .new_var("debug", "Debug build", Some(ValueDef::Bool), None)
+            .unwrap();
+        let _verbose = lib
+            .new_var("verbose", "Verbose output", Some(ValueDef::Bool), None)
+            .unwrap();
+
+        let all = root.new_goal("all", "Build all").unwrap();
+        let build = lib.new_goal("build", "Build library").unwrap();
+        let src = Artifact::<Input, Actual>::new(&root, "lib.c", "").unwrap();
+        let obj = Artifact::<Output, Actual>::new(&root, "lib.a", "").unwrap();
+        NoRule::new_raw(
+            once(src.into_kind_any()).collect(),
+            once(obj.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        NoRule::new_raw(
+            once(obj.input().into_kind_any()).collect(),
+            once(build.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        NoRule::new_raw(
+            once(build.input().into_kind_any()).collect(),
+            once(all.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+
+        let mut dump =
+            serde_json::to_value(Dump::new(&root, &|name| name != "lib.verbose")).unwrap();
+        // the identifiers and order of rules is not stable
+        let rules = dump["rules"].as_array_mut().unwrap();
+        for rule in rules.iter_mut() {
+            assert!(rule["id"].is_u64());
+            rule["id"] = json!(0);
+        }
+        rules.sort_by_key(|rule| rule["outputs"][0].as_str().unwrap().to_string());
+
+        assert_eq!(
+            dump,
+            json!({
+                "version": 1,
+                "scope": {
+                    "name": "",
+                    "description": "",
+                    "variables": [{
+                        "name": "cc",
+                        "description": "C compiler",
+                        "definition": { "type": "any" },
+                        "value": "clang",
+                        "default": "gcc",
+                        "source": "cli",
+                    }],
+                    "goals": [{
+                        "name": "all",
+                        "description": "Build all",
+                        "inputs": ["lib.build"],
+                    }],
+                    "scopes": [{
+                        "name": "lib",
+                        "description": "Library",
+                        "variables": [{
+                            "name": "lib.debug",
+                            "description": "Debug build",
+                            "definition": { "type": "bool" },
+                            "value": true,
+                            "default": false,
+                            "source": "config",
+                        }],
+                        "goals": [{
+                            "name": "lib.build",
+                            "description": "Build library",
+                            "inputs": ["lib.a"],
+                        }],
+                        "scopes": [],
+                    }],
+                },
+                "rules": [
+                    {
+                        "id": 0,
+                        "state": "processed",
+                        "inputs": ["lib.build"],
+                        "outputs": ["all"],
+                    },
+                    {
+                        "id": 0,
+                        "state": "processed",
+                        "inputs": ["lib.c"],
+                        "outputs": ["lib.a"],
+                    },
+                    {
+                        "id": 0,
+                        "state": "processed",
+                        "inputs": ["lib.a"],
+                        "outputs": ["lib.build"],
+                    },
+                ],
+            })
+        );
+    }
+}
@@ -1,3 +1,7 @@
+use crate::{
+    system::{read_file, Path},
+    Result,
+};
 use base64::{encode_config_buf, URL_SAFE_NO_PAD as ENCODE_PRESET};
 use blake3::Hasher as State;
 use std::hash::{Hash, Hasher};
@@ -79,4 +83,11 @@ impl DataHasher {
         this.hash(val);
         this.finish_base64_to_string(out);
     }
+
+    pub async fn hash_file_base64_string(path: impl AsRef<Path>) -> Result<String> {
+        let data = read_file(path.as_ref()).await?;
+        let mut this = Self::default();
+        this.write(&data);
+        Ok(this.finish_base64_string())
+    }
 }
@@ -0,0 +1,407 @@
+use crate::{
+    qjs,
+    system::{create_dir_all, read_file, Path, PathBuf},
+    Map, Mut, Ref, Result, Rule, Set, Time,
+};
+use async_std::{fs::OpenOptions, io::prelude::WriteExt};
+use derive_deref::Deref;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    time::UNIX_EPOCH,
+};
+
+/// The name of build history file in destination directory
+pub const HISTORY_FILE: &str = ".gear_history";
+
+/// Default number of last builds which is taken into account
+pub const HISTORY_BUILDS: usize = 10;
+
+/// Default number of rules in each list of statistics
+pub const HISTORY_RULES: usize = 10;
+
+/// The record of rule processing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// The identifier of build
+    pub build: u64,
+    /// The identifier of rule
+    pub rule: String,
+    /// The outputs of rule
+    pub outputs: Vec<String>,
+    /// The time when rule processing started
+    pub start: Time,
+    /// The time when rule processing ended
+    pub end: Time,
+    /// The result of rule processing
+    pub success: bool,
+    /// The number of diagnostics for each severity
+    pub diagnostics: Map<String, usize>,
+}
+
+impl HistoryEntry {
+    /// The duration of rule processing in seconds
+    pub fn duration(&self) -> f64 {
+        self.end
+            .duration_since(self.start)
+            .unwrap_or_default()
+            .as_secs_f64()
+    }
+}
+
+/// The statistics of rule processing across builds
+#[derive(Debug, Clone, Serialize, qjs::IntoJs)]
+pub struct RuleStats {
+    /// The identifier of rule
+    pub rule: String,
+    /// The outputs of rule
+    pub outputs: Vec<String>,
+    /// Number of rule invocations
+    pub runs: usize,
+    /// Number of failed invocations
+    pub failures: usize,
+    /// Number of result changes between consecutive invocations
+    pub flips: usize,
+    /// Average duration in seconds
+    pub average: f64,
+    /// Maximum duration in seconds
+    pub max: f64,
+}
+
+/// The statistics of last builds
+#[derive(Debug, Clone, Serialize, qjs::IntoJs)]
+pub struct HistoryStats {
+    /// Number of builds which is taken into account
+    pub builds: usize,
+    /// The rules ordered by average duration
+    pub slowest: Vec<RuleStats>,
+    /// The rules ordered by number of result changes
+    pub flakiest: Vec<RuleStats>,
+}
+
+impl Display for RuleStats {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{:>9.3}s avg {:>9.3}s max {:>4} run(s) {:>4} failed {:>4} flip(s)  ",
+            self.average, self.max, self.runs, self.failures, self.flips
+        )?;
+        if self.outputs.is_empty() {
+            write!(f, "Rule #{}", self.rule)
+        } else {
+            for (index, output) in self.outputs.iter().enumerate() {
+                if index > 0 {
+                    ", ".fmt(f)?;
+                }
+                write!(f, "`{}`", output)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl Display for HistoryStats {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        writeln!(f, "Slowest rules across last {} build(s):", self.builds)?;
+        for stats in &self.slowest {
+            writeln!(f, "    {}", stats)?;
+        }
+        writeln!(f, "Flakiest rules across last {} build(s):", self.builds)?;
+        for stats in &self.flakiest {
+            writeln!(f, "    {}", stats)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Internal {
+    path: Mut<Option<PathBuf>>,
+    build: Mut<u64>,
+    entries: Mut<Vec<HistoryEntry>>,
+}
+
+/// The append-only log of rule processing
+#[derive(Default, Clone, Deref)]
+pub struct History(Ref<Internal>);
+
+impl History {
+    /// Open history in specified directory
+    pub async fn open(&self, dir: impl AsRef<Path>) -> Result<()> {
+        *self.0.path.write() = Some(dir.as_ref().join(HISTORY_FILE));
+        Ok(())
+    }
+
+    /// Start recording of new build
+    pub fn begin(&self) {
+        *self.0.build.write() = Time::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.0.entries.write().clear();
+    }
+
+    /// Record rule processing
+    pub fn record(&self, rule: &Rule, start: Time, end: Time, success: bool) {
+        let mut diagnostics = Map::default();
+        for diagnostic in &rule.diagnostics().0 {
+            *diagnostics
+                .entry(diagnostic.severity.as_str().into())
+                .or_default() += 1;
+        }
+        let entry = HistoryEntry {
+            build: *self.0.build.read(),
+            rule: rule.id().to_string(),
+            outputs: rule
+                .outputs()
+                .into_iter()
+                .map(|output| output.name().clone())
+                .collect(),
+            start,
+            end,
+            success,
+            diagnostics,
+        };
+        self.0.entries.write().push(entry);
+    }
+
+    /// Append recorded entries to history file
+    pub async fn save(&self) -> Result<()> {
+        let path = if let Some(path) = &*self.0.path.read() {
+            path.clone()
+        } else {
+            return Ok(());
+        };
+        let mut raw = Vec::new();
+        for entry in self.0.entries.write().drain(..) {
+            serde_json::to_writer(&mut raw, &entry)?;
+            raw.push(b'\n');
+        }
+        if raw.is_empty() {
+            return Ok(());
+        }
+        log::debug!("Append build history `{}`", path.display());
+        if let Some(dir) = path.parent() {
+            if !dir.is_dir().await {
+                create_dir_all(dir).await?;
+            }
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(&raw).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Load entries of last builds
+    pub async fn load(&self, builds: usize) -> Result<Vec<HistoryEntry>> {
+        let path = if let Some(path) = &*self.0.path.read() {
+            path.clone()
+        } else {
+            return Ok(Vec::new());
+        };
+        if !path.is_file().await {
+            return Ok(Vec::new());
+        }
+        let raw = read_file(&path).await?;
+        let mut entries = Vec::new();
+        for line in raw.split(|c| *c == b'\n').filter(|line| !line.is_empty()) {
+            match serde_json::from_slice::<HistoryEntry>(line) {
+                Ok(entry) => entries.push(entry),
+                Err(error) => log::warn!(
+                    "Broken entry in build history `{}` due to: {}. Skipped.",
+                    path.display(),
+                    error
+                ),
+            }
+        }
+        let last = entries
+            .iter()
+            .rev()
+            .map(|entry| entry.build)
+            .collect::<Set<_>>()
+            .into_iter()
+            .take(builds)
+            .collect::<Set<_>>();
+        entries.retain(|entry| last.contains(&entry.build));
+        Ok(entries)
+    }
+
+    /// Get statistics of last builds
+    ///
+    /// At most `limit` rules will be included into each list.
+    pub async fn stats(&self, builds: usize, limit: usize) -> Result<HistoryStats> {
+        let entries = self.load(builds).await?;
+        let builds = entries.iter().map(|entry| entry.build).collect::<Set<_>>();
+
+        let mut rules = Map::<&str, Vec<&HistoryEntry>>::default();
+        for entry in &entries {
+            rules.entry(&entry.rule).or_default().push(entry);
+        }
+
+        let stats = rules
+            .into_iter()
+            .map(|(rule, entries)| {
+                let durations = entries
+                    .iter()
+                    .map(|entry| entry.duration())
+                    .collect::<Vec<_>>();
+                RuleStats {
+                    rule: rule.into(),
+                    outputs: entries[entries.len() - 1].outputs.clone(),
+                    runs: entries.len(),
+                    failures: entries.iter().filter(|entry| !entry.success).count(),
+                    flips: entries
+                        .windows(2)
+                        .filter(|pair| pair[0].success != pair[1].success)
+                        .count(),
+                    average: durations.iter().sum::<f64>() / durations.len() as f64,
+                    max: durations.iter().copied().fold(0.0, f64::max),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut slowest = stats.clone();
+        slowest.sort_by(|a, b| b.average.total_cmp(&a.average));
+        slowest.truncate(limit);
+
+        let mut flakiest = stats
+            .into_iter()
+            .filter(|stats| stats.flips > 0)
+            .collect::<Vec<_>>();
+        flakiest.sort_by(|a, b| {
+            b.flips
+                .cmp(&a.flips)
+                .then_with(|| b.failures.cmp(&a.failures))
+        });
+        flakiest.truncate(limit);
+
+        Ok(HistoryStats {
+            builds: builds.len(),
+            slowest,
+            flakiest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Artifact, ArtifactStore, Duration, NoRule, Output, Phony};
+
+    fn entry(build: u64, rule: &str, secs: u64, success: bool) -> HistoryEntry {
+        let start = UNIX_EPOCH + Duration::from_secs(build * 1000);
+        HistoryEntry {
+            build,
+            rule: rule.into(),
+            outputs: vec![format!("{}.o", rule)],
+            start,
+            end: start + Duration::from_secs(secs),
+            success,
+            diagnostics: Default::default(),
+        }
+    }
+
+    async fn history(entries: &[HistoryEntry]) -> (tempfile::TempDir, History) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut raw = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut raw, entry).unwrap();
+            raw.push(b'\n');
+        }
+        raw.extend_from_slice(b"broken\n");
+        crate::system::write_file(dir.path().join(HISTORY_FILE), raw)
+            .await
+            .unwrap();
+        let history = History::default();
+        history.open(dir.path().to_str().unwrap()).await.unwrap();
+        (dir, history)
+    }
+
+    #[async_std::test]
+    async fn load() {
+        let (_dir, history) = history(&[
+            entry(1, "a", 1, true),
+            entry(2, "a", 1, true),
+            entry(2, "b", 1, true),
+            entry(3, "a", 1, true),
+        ])
+        .await;
+        let builds = |entries: Vec<HistoryEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.build)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(builds(history.load(2).await.unwrap()), [2, 2, 3]);
+        assert_eq!(
+            builds(history.load(HISTORY_BUILDS).await.unwrap()),
+            [1, 2, 2, 3]
+        );
+    }
+
+    #[async_std::test]
+    async fn stats() {
+        let (_dir, history) = history(&[
+            entry(1, "a", 2, true),
+            entry(1, "b", 1, true),
+            entry(1, "c", 2, true),
+            entry(2, "a", 4, false),
+            entry(2, "b", 1, false),
+            entry(3, "a", 3, true),
+            entry(3, "b", 1, false),
+        ])
+        .await;
+        let stats = history.stats(HISTORY_BUILDS, 2).await.unwrap();
+        assert_eq!(stats.builds, 3);
+
+        fn rules(stats: &[RuleStats]) -> Vec<&str> {
+            stats.iter().map(|stats| stats.rule.as_str()).collect()
+        }
+        assert_eq!(rules(&stats.slowest), ["a", "c"]);
+        let a = &stats.slowest[0];
+        assert_eq!(a.outputs, ["a.o"]);
+        assert_eq!((a.runs, a.failures, a.flips), (3, 1, 2));
+        assert!((a.average - 3.0).abs() < 1e-9);
+        assert!((a.max - 4.0).abs() < 1e-9);
+
+        // the rules which result is never changed is not flaky
+        assert_eq!(rules(&stats.flakiest), ["a", "b"]);
+        let b = &stats.flakiest[1];
+        assert_eq!((b.runs, b.failures, b.flips), (3, 2, 1));
+    }
+
+    #[async_std::test]
+    async fn record() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::default();
+        history.open(dir.path().to_str().unwrap()).await.unwrap();
+        let store = ArtifactStore::default();
+        let goal = Artifact::<Output, Phony>::new(&store, "all", "").unwrap();
+        NoRule::new_raw(
+            Default::default(),
+            std::iter::once(goal.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+        let rule = goal.rule().unwrap();
+
+        for success in &[true, false] {
+            history.begin();
+            let start = Time::now();
+            history.record(&rule, start, start, *success);
+            history.save().await.unwrap();
+            // builds is identified by start time in milliseconds
+            async_std::task::sleep(Duration::from_millis(2)).await;
+        }
+
+        let entries = history.load(HISTORY_BUILDS).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_ne!(entries[0].build, entries[1].build);
+        assert_eq!(entries[0].rule, rule.id().to_string());
+        assert!(entries[0].success);
+        assert!(!entries[1].success);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{
+    system::{create_dir_all, Path, TempFile},
+    Ref, Result,
+};
+use async_io::Async;
+use nix::{fcntl::OFlag, unistd::pipe};
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    os::unix::{fs::OpenOptionsExt, io::FromRawFd},
+    str::FromStr,
+};
+
+/// The environment variables which may contain jobserver options
+const MAKEFLAGS_VARS: &[&str] = &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"];
+
+/// The style of jobserver which is created for child processes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JobServerStyle {
+    /// Anonymous pipe which descriptors is inherited by child processes
+    #[default]
+    Pipe,
+    /// Named pipe which is opened by child processes (GNU make 4.4 and newer)
+    Fifo,
+}
+
+impl FromStr for JobServerStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s {
+            "pipe" => Self::Pipe,
+            "fifo" => Self::Fifo,
+            _ => return Err(format!("Unknown jobserver style `{}`", s)),
+        })
+    }
+}
+
+pub struct Internal {
+    reader: Async<File>,
+    writer: File,
+    makeflags: String,
+    /// The inherited descriptors of anonymous pipe
+    _pipe: Option<(File, File)>,
+    _fifo: Option<TempFile>,
+}
+
+/// GNU make compatible jobserver
+///
+/// Gear either creates new jobserver which is shared with child processes via `MAKEFLAGS`
+/// or joins to existing one when it is launched from make.
+#[derive(Clone)]
+pub struct JobServer(Ref<Internal>);
+
+/// The token which is acquired from jobserver
+///
+/// The token will be released back when dropped.
+pub struct Token {
+    server: JobServer,
+    byte: u8,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        if let Err(error) = (&self.server.0.writer).write_all(&[self.byte]) {
+            log::error!("Unable to release jobserver token due to: {}", error);
+        }
+    }
+}
+
+impl JobServer {
+    /// Create new jobserver with specified number of jobs
+    ///
+    /// The named pipe will be created in specified directory when fifo style is used.
+    pub async fn create(dir: impl AsRef<Path>, jobs: usize, style: JobServerStyle) -> Result<Self> {
+        let (reader, writer, makeflags, pipe, fifo) = match style {
+            JobServerStyle::Pipe => {
+                // the descriptors without close-on-exec flag will be inherited by child processes
+                let (read_fd, write_fd) = pipe().map_err(|error| {
+                    format!("Unable to create jobserver pipe due to: {}", error)
+                })?;
+                let pipe = unsafe { (File::from_raw_fd(read_fd), File::from_raw_fd(write_fd)) };
+                let (reader, writer) = Self::reopen(read_fd, write_fd)?;
+                let makeflags = format!(
+                    "-j{} --jobserver-fds={1},{2} --jobserver-auth={1},{2}",
+                    jobs, read_fd, write_fd
+                );
+                (reader, writer, makeflags, Some(pipe), None)
+            }
+            JobServerStyle::Fifo => {
+                let dir = dir.as_ref();
+                if !dir.is_dir().await {
+                    create_dir_all(dir).await?;
+                }
+                let dir = dir.canonicalize().await?;
+                let fifo = TempFile::new(&dir, true).await?;
+                let file = Self::open(fifo.path(), true, true)?;
+                let makeflags =
+                    format!("-j{} --jobserver-auth=fifo:{}", jobs, fifo.path().display());
+                (file.try_clone()?, file, makeflags, None, Some(fifo))
+            }
+        };
+        // one job is implicit so it does not need token
+        for _ in 1..jobs {
+            (&writer).write_all(b"+")?;
+        }
+        log::debug!("Create jobserver `{}`", makeflags);
+        Ok(Self(Ref::new(Internal {
+            reader: Async::new(reader)?,
+            writer,
+            makeflags,
+            _pipe: pipe,
+            _fifo: fifo,
+        })))
+    }
+
+    /// Join to jobserver using options from environment
+    pub fn from_env() -> Result<Option<Self>> {
+        for var in MAKEFLAGS_VARS {
+            if let Ok(makeflags) = env::var(var) {
+                if let Some(this) = Self::from_makeflags(&makeflags)? {
+                    return Ok(Some(this));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn from_makeflags(makeflags: &str) -> Result<Option<Self>> {
+        let auth = if let Some(auth) = makeflags.split_whitespace().find_map(|flag| {
+            flag.strip_prefix("--jobserver-auth=")
+                .or_else(|| flag.strip_prefix("--jobserver-fds="))
+        }) {
+            auth
+        } else {
+            return Ok(None);
+        };
+
+        let (reader, writer) = if let Some(path) = auth.strip_prefix("fifo:") {
+            let file = Self::open(Path::new(path), true, true)?;
+            (file.try_clone()?, file)
+        } else {
+            let mut fds = auth.split(',');
+            match (
+                fds.next().and_then(|fd| fd.parse().ok()),
+                fds.next().and_then(|fd| fd.parse().ok()),
+            ) {
+                (Some(read_fd), Some(write_fd)) => match Self::reopen(read_fd, write_fd) {
+                    Ok(files) => files,
+                    Err(_) => {
+                        log::warn!(
+                            "Unable to use jobserver `{}`. Seems it is not inherited.",
+                            auth
+                        );
+                        return Ok(None);
+                    }
+                },
+                _ => return Err(format!("Invalid jobserver options `{}`", auth).into()),
+            }
+        };
+
+        log::debug!("Join jobserver `{}`", auth);
+        Ok(Some(Self(Ref::new(Internal {
+            reader: Async::new(reader)?,
+            writer,
+            makeflags: makeflags.into(),
+            _pipe: None,
+            _fifo: None,
+        }))))
+    }
+
+    /// Reopen pipe descriptors to get own non-blocking ones
+    ///
+    /// The blocking mode of descriptors which is shared with child processes should not be changed.
+    #[cfg(target_os = "linux")]
+    fn reopen(read_fd: i32, write_fd: i32) -> Result<(File, File)> {
+        let reader = Self::open(
+            Path::new(&format!("/proc/self/fd/{}", read_fd)),
+            true,
+            false,
+        )?;
+        let writer = Self::open(
+            Path::new(&format!("/proc/self/fd/{}", write_fd)),
+            false,
+            true,
+        )?;
+        Ok((reader, writer))
+    }
+
+    /// Duplicate pipe descriptors because reopening requires `/proc` filesystem
+    ///
+    /// The duplicated descriptors shares blocking mode with the inherited ones.
+    #[cfg(not(target_os = "linux"))]
+    fn reopen(read_fd: i32, write_fd: i32) -> Result<(File, File)> {
+        log::warn!("Jobserver pipe will be switched to non-blocking mode for child processes too");
+        let dup = |fd| {
+            nix::unistd::dup(fd)
+                .map(|fd| unsafe { File::from_raw_fd(fd) })
+                .map_err(|error| {
+                    format!("Unable to duplicate jobserver descriptor due to: {}", error)
+                })
+        };
+        Ok((dup(read_fd)?, dup(write_fd)?))
+    }
+
+    fn open(path: &Path, read: bool, write: bool) -> Result<File> {
+        Ok(OpenOptions::new()
+            .read(read)
+            .write(write)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(path)?)
+    }
+
+    /// The make flags which should be passed to child processes
+    pub fn makeflags(&self) -> &str {
+        &self.0.makeflags
+    }
+
+    /// Merge jobserver options into existing make flags
+    ///
+    /// The jobs and jobserver options will be replaced, other flags and variables is kept as is.
+    pub fn merge_makeflags(&self, makeflags: &str) -> String {
+        merge_makeflags(makeflags, &self.0.makeflags)
+    }
+
+    /// Try to acquire token without waiting
+    pub fn try_acquire(&self) -> Result<Option<Token>> {
+        let mut byte = [0u8];
+        match self.0.reader.get_ref().read(&mut byte) {
+            Ok(1) => Ok(Some(self.token(byte[0]))),
+            Ok(_) => Err("Jobserver pipe closed unexpectedly".into()),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(error) if error.kind() == ErrorKind::Interrupted => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Acquire token
+    ///
+    /// Waits until token will be available. It is safe to drop pending future.
+    pub async fn acquire(&self) -> Result<Token> {
+        let mut byte = [0u8];
+        loop {
+            match self
+                .0
+                .reader
+                .read_with(|mut reader| reader.read(&mut byte))
+                .await
+            {
+                Ok(1) => return Ok(self.token(byte[0])),
+                Ok(_) => return Err("Jobserver pipe closed unexpectedly".into()),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn token(&self, byte: u8) -> Token {
+        Token {
+            server: self.clone(),
+            byte,
+        }
+    }
+}
+
+fn merge_makeflags(makeflags: &str, jobserver: &str) -> String {
+    // the variables goes after flags and separated by ` -- `
+    let (flags, vars) = if let Some(vars) = makeflags.strip_prefix("-- ") {
+        ("", Some(vars))
+    } else if let Some(pos) = makeflags.find(" -- ") {
+        (&makeflags[..pos], Some(&makeflags[pos + 4..]))
+    } else {
+        (makeflags, None)
+    };
+    let mut words = flags
+        .split_whitespace()
+        .filter(|word| {
+            !(word.starts_with("-j")
+                || word.starts_with("--jobs")
+                || word.starts_with("--jobserver-auth=")
+                || word.starts_with("--jobserver-fds="))
+        })
+        .collect::<Vec<_>>();
+    words.push(jobserver);
+    if let Some(vars) = vars {
+        words.push("--");
+        words.push(vars);
+    }
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let jobserver = "-j4 --jobserver-auth=fifo:/tmp/fifo";
+        assert_eq!(merge_makeflags("", jobserver), jobserver);
+        assert_eq!(
+            merge_makeflags("k -j2 --jobserver-fds=3,4 --jobserver-auth=3,4", jobserver),
+            "k -j4 --jobserver-auth=fifo:/tmp/fifo"
+        );
+        assert_eq!(
+            merge_makeflags("s -- CC=clang CFLAGS=-O2", jobserver),
+            "s -j4 --jobserver-auth=fifo:/tmp/fifo -- CC=clang CFLAGS=-O2"
+        );
+        assert_eq!(
+            merge_makeflags("-- V=1", jobserver),
+            "-j4 --jobserver-auth=fifo:/tmp/fifo -- V=1"
+        );
+    }
+
+    #[async_std::test]
+    async fn fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = JobServer::create(dir.path().to_str().unwrap(), 3, JobServerStyle::Fifo)
+            .await
+            .unwrap();
+        assert!(server.makeflags().contains("--jobserver-auth=fifo:"));
+
+        let client = JobServer::from_makeflags(&format!("k {}", server.makeflags()))
+            .unwrap()
+            .unwrap();
+        let first = client.try_acquire().unwrap().unwrap();
+        let _second = server.acquire().await.unwrap();
+        assert!(client.try_acquire().unwrap().is_none());
+
+        let waiting = async_std::task::spawn_local({
+            let client = client.clone();
+            async move { client.acquire().await.map(|_| ()) }
+        });
+        drop(first);
+        waiting.await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn pipe() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = JobServer::create(dir.path().to_str().unwrap(), 2, JobServerStyle::Pipe)
+            .await
+            .unwrap();
+        assert!(server.makeflags().contains("--jobserver-fds="));
+        assert!(server.makeflags().contains("--jobserver-auth="));
+
+        let client = JobServer::from_makeflags(server.makeflags())
+            .unwrap()
+            .unwrap();
+        let token = client.acquire().await.unwrap();
+        assert!(server.try_acquire().unwrap().is_none());
+        drop(token);
+        assert!(server.try_acquire().unwrap().is_some());
+    }
+
+    #[test]
+    fn absent() {
+        assert!(JobServer::from_makeflags("").unwrap().is_none());
+        assert!(JobServer::from_makeflags("k -- CC=gcc").unwrap().is_none());
+        // not inherited descriptors
+        assert!(JobServer::from_makeflags("-j2 --jobserver-auth=1000,1001")
+            .unwrap()
+            .is_none());
+        assert!(JobServer::from_makeflags("-j2 --jobserver-auth=r,w").is_err());
+    }
+}
@@ -1,17 +1,29 @@
 mod artifact;
+mod cache;
+mod cancel;
+mod clean;
 mod compiler;
 mod console;
+mod database;
 mod diagnostic;
 mod directory;
+mod dump;
 mod extensions;
 mod hasher;
+mod history;
+#[cfg(all(unix, feature = "jobserver"))]
+mod jobserver;
 mod processor;
+mod query;
 mod refs;
+#[cfg(feature = "http-client")]
+mod remote;
 mod result;
 mod rule;
 mod scope;
 mod store;
 pub mod system;
+mod trace;
 mod utils;
 mod variable;
 
@@ -23,19 +35,38 @@ pub use rquickjs as qjs;
 pub use std::time::{Duration, SystemTime as Time};
 pub use weak_table::traits::{WeakElement, WeakKey};
 
-pub use artifact::{Actual, Artifact, ArtifactStore, Input, Output, Phony, WeakArtifact};
+pub use artifact::{
+    Actual, Artifact, ArtifactKind, ArtifactStore, ArtifactType, Input, Output, Phony, Reason,
+    WeakArtifact,
+};
+pub use cache::{Cache, CacheEntry, CacheStats};
+pub use cancel::{Cancel, Cancelled};
+pub use database::{Database, InputEntry, OutputEntry, DATABASE_FILE};
 pub use diagnostic::{
     Diagnostic, Diagnostics, FixingSuggestion, Location, Severity, TextPoint, TextSpan,
 };
 pub use directory::Directory;
+pub use dump::Dump;
 pub use hasher::DataHasher;
-pub use processor::RuleStateChange;
-pub use rule::{JsRule, NoRule, Rule, RuleApi, RuleId, RuleState};
+pub use history::{
+    History, HistoryEntry, HistoryStats, RuleStats, HISTORY_BUILDS, HISTORY_FILE, HISTORY_RULES,
+};
+#[cfg(all(unix, feature = "jobserver"))]
+pub use jobserver::{JobServer, JobServerStyle, Token};
+pub use processor::{ProcessOpts, RuleStateChange};
+pub use query::{ArtifactInfo, Query};
+#[cfg(feature = "http-client")]
+pub use remote::{RemoteCache, RemoteEntry, RemoteFile};
+pub use rule::{
+    Invocation, JsRule, NoRule, Rule, RuleApi, RuleId, RuleOptions, RulePolicy, RuleSignature,
+    RuleState,
+};
 pub use scope::Scope;
 pub use store::Store;
+pub use trace::Trace;
 pub use variable::{
-    Value, ValueDef, ValueError, ValueResult, ValueStore, Variable, VariableDef, VariableStore,
-    WeakVariable, WeakVariableSet,
+    Value, ValueDef, ValueError, ValueResult, ValueSource, ValueStore, Variable, VariableDef,
+    VariableStore, WeakVariable, WeakVariableSet,
 };
 
 pub use console::Js as ConsoleJs;
@@ -11,7 +11,7 @@ use async_std::{
     fs::File,
     io::ReadExt,
 };
-use cmdline::{Args, Print};
+use cmdline::{Args, Print, QueryOutput};
 use gear::{qjs, Map, Ref, Result, Set};
 use std::env;
 
@@ -80,9 +80,31 @@ impl Main {
         let props = Ref::new(props);
         let variables = gear::VariableStore::new(values, args.get_vars());
         let artifacts = gear::ArtifactStore::default();
-        let store = gear::Store::new(variables, artifacts);
+        artifacts
+            .database
+            .open(gear::system::Path::new(&props.dest))
+            .await?;
+        artifacts
+            .history
+            .open(gear::system::Path::new(&props.dest))
+            .await?;
+        if let Some((dir, size)) = args.get_cache() {
+            artifacts.cache.open(dir, size).await?;
+        }
+        #[cfg(feature = "http-client")]
+        if let Some(url) = &args.remote_cache {
+            artifacts
+                .cache
+                .set_remote(gear::RemoteCache::new(url, !args.remote_cache_read_only)?);
+        }
+
+        let store = gear::Store::new(variables, artifacts.clone());
         let scope = gear::Scope::new_root(store);
         let (sender, receiver) = unbounded();
+        let cancel = gear::Cancel::default();
+
+        #[cfg(feature = "ctrlc")]
+        Self::handle_signals(cancel.clone())?;
 
         #[cfg(feature = "webui")]
         if let Some(url) = &args.webui {
@@ -90,7 +112,7 @@ impl Main {
         }
 
         loop {
-            let state = State::new(props.clone(), scope.clone(), sender.clone())?;
+            let state = State::new(props.clone(), scope.clone(), sender.clone(), cancel.clone())?;
 
             state.load_rules().await?;
 
@@ -98,29 +120,42 @@ impl Main {
                 args.gen_completions();
             } else if let Some(print) = args.get_print() {
                 state.print_db(print).await?;
+            } else if let Some(query) = &args.query {
+                state.query(query, args.query_output).await?;
+            } else if let Some(builds) = args.get_history() {
+                state.print_history(builds).await?;
+            } else if args.clean {
+                state.clean_goals(args.dry_run).await?;
+            } else if args.clean_dead {
+                state.clean_dead(args.dry_run).await?;
             } else {
                 if let Err(error) = state.sender.send(Event::RulesUpdate).await {
                     log::error!("Unable to send rules update event due to: {}", error);
                 }
 
-                let jobs = args.get_jobs();
+                #[cfg(all(unix, feature = "jobserver"))]
+                if !args.dry_run {
+                    Self::setup_jobserver(&artifacts, &props, &args).await?;
+                }
+
+                let opts = args.get_process_opts();
 
                 #[cfg(not(feature = "watch"))]
-                state.build_rules(jobs, args.dry_run).await?;
+                state.build_rules(opts).await?;
 
                 #[cfg(feature = "watch")]
                 if args.watch {
                     // do not panic when rules fails to build completely
-                    if let Err(error) = state.build_rules(jobs, args.dry_run).await {
+                    if let Err(error) = state.build_rules(opts.clone()).await {
                         eprintln!("{}", error);
                     }
 
-                    if state.watch_inputs(jobs, args.dry_run).await? {
+                    if !cancel.is_cancelled() && state.watch_inputs(opts).await? {
                         log::debug!("Reloading rules");
                         continue;
                     }
                 } else {
-                    state.build_rules(jobs, args.dry_run).await?;
+                    state.build_rules(opts).await?;
                 }
             }
 
@@ -129,6 +164,58 @@ impl Main {
 
         Ok(())
     }
+
+    /// Join to jobserver of parent make or create new one for child processes
+    #[cfg(all(unix, feature = "jobserver"))]
+    async fn setup_jobserver(
+        artifacts: &gear::ArtifactStore,
+        props: &Props,
+        args: &Args,
+    ) -> Result<()> {
+        if artifacts.jobserver.read().is_some() {
+            return Ok(());
+        }
+        let jobserver = if let Some(jobserver) = gear::JobServer::from_env()? {
+            jobserver
+        } else if args.get_jobs() > 1 {
+            let jobserver = gear::JobServer::create(
+                gear::system::Path::new(&props.dest),
+                args.get_jobs(),
+                args.jobserver_style,
+            )
+            .await?;
+            // child processes will inherit jobserver
+            let makeflags = env::var("MAKEFLAGS").unwrap_or_default();
+            env::set_var("MAKEFLAGS", jobserver.merge_makeflags(&makeflags));
+            jobserver
+        } else {
+            return Ok(());
+        };
+        *artifacts.jobserver.write() = Some(jobserver);
+        Ok(())
+    }
+
+    /// Cancel processing on interrupt and exit immediately on repeated interrupt
+    ///
+    /// The running programs is terminated in both cases because it does not receive interrupts from terminal.
+    #[cfg(feature = "ctrlc")]
+    fn handle_signals(cancel: gear::Cancel) -> Result<()> {
+        use futures::StreamExt;
+
+        let mut signals = async_ctrlc::CtrlC::new()
+            .map_err(|error| format!("Unable to handle interrupts due to: {}", error))?;
+        gear::system::spawn(async move {
+            signals.next().await;
+            log::warn!("Interrupted. Cancelling...");
+            cancel.cancel();
+            gear::system::kill_process_groups();
+            signals.next().await;
+            log::error!("Interrupted again. Exiting immediately.");
+            gear::system::kill_process_groups();
+            std::process::exit(130);
+        });
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -169,10 +256,16 @@ struct State {
     ctx: qjs::Context,
     compile: qjs::Compile,
     scope: gear::Scope,
+    cancel: gear::Cancel,
 }
 
 impl State {
-    pub fn new(props: Ref<Props>, scope: gear::Scope, sender: Sender<Event>) -> Result<Self> {
+    pub fn new(
+        props: Ref<Props>,
+        scope: gear::Scope,
+        sender: Sender<Event>,
+        cancel: gear::Cancel,
+    ) -> Result<Self> {
         let (rt, ctx, compile) = Self::init_js(&props.paths)?;
 
         Ok(Self {
@@ -182,6 +275,7 @@ impl State {
             ctx,
             compile,
             scope,
+            cancel,
         })
     }
 
@@ -297,16 +391,78 @@ impl State {
                     &|name: &str| self.match_goal(name)
                 ))
             ),
+            Print::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(
+                    &gear::Dump::new(&self.scope, &|name: &str| self.match_goal(name))
+                )?
+            ),
         }
         Ok(())
     }
 
-    pub async fn build_rules(&self, jobs: usize, dry_run: bool) -> Result<()> {
+    pub async fn query(&self, query: &str, output: QueryOutput) -> Result<()> {
+        let store: &gear::ArtifactStore = self.scope.as_ref();
+        let query: gear::Query = query.parse()?;
+        log::debug!("Query {:?}", query);
+        let artifacts = query.eval(store)?;
+        match output {
+            QueryOutput::Names => {
+                for artifact in artifacts {
+                    println!("{}", artifact.name());
+                }
+            }
+            QueryOutput::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(
+                    &artifacts
+                        .iter()
+                        .map(gear::ArtifactInfo::from)
+                        .collect::<Vec<_>>()
+                )?
+            ),
+        }
+        Ok(())
+    }
+
+    pub async fn print_history(&self, builds: usize) -> Result<()> {
+        let store: &gear::ArtifactStore = self.scope.as_ref();
+        print!(
+            "{}",
+            store.history.stats(builds, gear::HISTORY_RULES).await?
+        );
+        Ok(())
+    }
+
+    pub async fn clean_goals(&self, dry_run: bool) -> Result<()> {
+        let store: &gear::ArtifactStore = self.scope.as_ref();
+        let goals = self.props.goals.iter().collect::<Vec<_>>();
+        Self::print_removed(store.clean_goals(&goals, dry_run).await?, dry_run);
+        Ok(())
+    }
+
+    pub async fn clean_dead(&self, dry_run: bool) -> Result<()> {
+        let store: &gear::ArtifactStore = self.scope.as_ref();
+        Self::print_removed(store.clean_dead(dry_run).await?, dry_run);
+        Ok(())
+    }
+
+    fn print_removed(files: Vec<String>, dry_run: bool) {
+        for file in files {
+            if dry_run {
+                println!("Would remove `{}`", file);
+            } else {
+                println!("Removed `{}`", file);
+            }
+        }
+    }
+
+    pub async fn build_rules(&self, opts: gear::ProcessOpts) -> Result<()> {
         log::debug!("Build goals: {:?}", self.props.goals);
         let store: &gear::ArtifactStore = self.scope.as_ref();
         let sender = self.sender.clone();
         store
-            .process(&self.props.goals, jobs, dry_run, move |event| {
+            .process(&self.props.goals, opts, &self.cancel, move |event| {
                 let sender = sender.clone();
                 async move {
                     if let Err(error) = sender.send(Event::RuleStateChange(event)).await {
@@ -318,7 +474,7 @@ impl State {
     }
 
     #[cfg(feature = "watch")]
-    pub async fn watch_inputs(&self, jobs: usize, dry_run: bool) -> Result<bool> {
+    pub async fn watch_inputs(&self, opts: gear::ProcessOpts) -> Result<bool> {
         use futures::StreamExt;
         use gear::system::Path;
 
@@ -357,7 +513,12 @@ impl State {
         log::trace!("Watch rules files: {:?}", modules);
 
         loop {
-            match events.next().await {
+            let event = if let Ok(event) = self.cancel.run(events.next()).await {
+                event
+            } else {
+                break;
+            };
+            match event {
                 Some(Ok(entries)) => {
                     let paths = entries
                         .iter()
@@ -387,7 +548,15 @@ impl State {
 
                     let store: &gear::ArtifactStore = self.scope.as_ref();
                     match store.update_sources(paths).await {
-                        Ok(true) => self.build_rules(jobs, dry_run).await?,
+                        Ok(true) => {
+                            // do not stop watching when rules fails to build completely
+                            if let Err(error) = self.build_rules(opts.clone()).await {
+                                eprintln!("{}", error);
+                            }
+                            if self.cancel.is_cancelled() {
+                                break;
+                            }
+                        }
                         Err(error) => {
                             log::error!("Errot then updating sources: {}", error);
                         }
@@ -1,17 +1,235 @@
-use crate::{Artifact, ArtifactStore, Result, Rule, RuleState, Set, Time};
+use crate::{
+    system::PathBuf, Artifact, ArtifactStore, Cancel, Database, Duration, Error, Input, Map,
+    Reason, Result, Rule, RuleId, RuleState, Set, Time, Trace,
+};
 use futures::future;
-use std::{collections::VecDeque, future::Future, iter::once};
+use std::{
+    cmp::Reverse, collections::BinaryHeap, fmt::Write, future::Future, iter::once, time::Instant,
+};
+
+/// Rules processing options
+#[derive(Debug, Clone)]
+pub struct ProcessOpts {
+    /// Number of jobs runs simultaneously
+    pub jobs: usize,
+    /// Number of failed rules to stop scheduling new rules (0 means infinity)
+    ///
+    /// The scheduling is not stopped by default (the command line uses fail-fast mode instead).
+    pub failures: usize,
+    /// Do not invoke rules
+    pub dry_run: bool,
+    /// Write timeline of build to file
+    pub trace: Option<PathBuf>,
+    /// Execute commands of rules in sandbox
+    pub sandbox: bool,
+    /// Report the reasons of rules scheduling
+    pub explain: bool,
+}
+
+impl Default for ProcessOpts {
+    fn default() -> Self {
+        Self {
+            jobs: 1,
+            failures: 0,
+            dry_run: false,
+            trace: None,
+            sandbox: false,
+            explain: false,
+        }
+    }
+}
 
 /// Changing rule state event
 #[derive(Clone)]
 pub struct RuleStateChange {
     pub rule: Rule,
     pub state: RuleState,
+    /// The number of processing attempt (starting from 1 when processing)
+    pub attempt: usize,
 }
 
 impl RuleStateChange {
     pub fn new(rule: Rule, state: RuleState) -> Self {
-        Self { rule, state }
+        Self {
+            rule,
+            state,
+            attempt: 0,
+        }
+    }
+
+    pub fn with_attempt(rule: Rule, state: RuleState, attempt: usize) -> Self {
+        Self {
+            rule,
+            state,
+            attempt,
+        }
+    }
+}
+
+/// The node of scheduled rules graph
+struct RuleNode {
+    rule: Rule,
+    /// Number of dependencies which is not processed yet
+    deps: usize,
+    /// Rules which depends on this rule
+    dependents: Vec<RuleId>,
+    /// Expected duration of rule processing
+    weight: Duration,
+    /// Expected duration of longest path from this rule to the end of build
+    priority: Duration,
+    /// The name of pool which limits concurrency
+    pool: Option<String>,
+}
+
+/// The graph of scheduled rules
+///
+/// The rules which dependencies is already processed will be picked
+/// in order of expected duration of longest path to the end of build.
+struct RuleGraph {
+    nodes: Map<RuleId, RuleNode>,
+    ready: BinaryHeap<(Duration, Reverse<usize>, RuleId)>,
+}
+
+impl RuleGraph {
+    fn new(rules: Vec<Rule>, db: &Database) -> Self {
+        let durations = rules
+            .iter()
+            .map(|rule| rule.duration(db))
+            .collect::<Vec<_>>();
+        let known = durations
+            .iter()
+            .filter_map(|duration| *duration)
+            .collect::<Vec<_>>();
+        // use average duration for rules which was never processed before
+        let default_weight = if known.is_empty() {
+            Duration::default()
+        } else {
+            known.iter().sum::<Duration>() / known.len() as u32
+        };
+
+        let mut nodes = rules
+            .into_iter()
+            .zip(durations)
+            .map(|(rule, duration)| {
+                let weight = duration.unwrap_or(default_weight);
+                let pool = rule.pool();
+                (
+                    rule.id(),
+                    RuleNode {
+                        rule,
+                        deps: 0,
+                        dependents: Vec::new(),
+                        weight,
+                        priority: weight,
+                        pool,
+                    },
+                )
+            })
+            .collect::<Map<_, _>>();
+
+        for index in 0..nodes.len() {
+            let (id, node) = nodes.get_index(index).unwrap();
+            let id = *id;
+            let deps = node
+                .rule
+                .deps()
+                .into_iter()
+                .filter_map(|input| input.rule())
+                .map(|rule| rule.id())
+                .filter(|dep| *dep != id && nodes.contains_key(dep))
+                .collect::<Set<_>>();
+            nodes[index].deps = deps.len();
+            for dep in deps {
+                nodes[&dep].dependents.push(id);
+            }
+        }
+
+        // topological order of rules
+        let mut order = Vec::with_capacity(nodes.len());
+        {
+            let mut deps = nodes.values().map(|node| node.deps).collect::<Vec<_>>();
+            let mut queue = (0..nodes.len())
+                .filter(|index| deps[*index] == 0)
+                .collect::<Vec<_>>();
+            while let Some(index) = queue.pop() {
+                order.push(index);
+                for dependent in &nodes[index].dependents {
+                    let dependent = nodes.get_index_of(dependent).unwrap();
+                    deps[dependent] -= 1;
+                    if deps[dependent] == 0 {
+                        queue.push(dependent);
+                    }
+                }
+            }
+        }
+
+        for index in order.into_iter().rev() {
+            let longest = nodes[index]
+                .dependents
+                .iter()
+                .map(|dependent| nodes[dependent].priority)
+                .max()
+                .unwrap_or_default();
+            let node = &mut nodes[index];
+            node.priority = node.weight + longest;
+        }
+
+        let ready = nodes
+            .values()
+            .enumerate()
+            .filter(|(_, node)| node.deps == 0)
+            .map(|(index, node)| (node.priority, Reverse(index), node.rule.id()))
+            .collect();
+
+        Self { nodes, ready }
+    }
+
+    /// Number of rules in graph
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Pick the next rule which is ready to process
+    ///
+    /// The rules which pools is not available will be skipped.
+    fn pop_ready(&mut self, available: impl Fn(Option<&str>) -> bool) -> Option<Rule> {
+        let mut skipped = Vec::new();
+        let mut picked = None;
+        while let Some(entry) = self.ready.pop() {
+            if available(self.nodes[&entry.2].pool.as_deref()) {
+                picked = Some(entry);
+                break;
+            }
+            skipped.push(entry);
+        }
+        self.ready.extend(skipped);
+        picked.map(|(priority, _, id)| {
+            let rule = self.nodes[&id].rule.clone();
+            log::trace!("Pick {} with priority {:?}", rule, priority);
+            rule
+        })
+    }
+
+    /// Return picked rule back to ready rules
+    #[cfg(all(unix, feature = "jobserver"))]
+    fn push_ready(&mut self, rule: &Rule) {
+        let id = rule.id();
+        let index = self.nodes.get_index_of(&id).unwrap();
+        self.ready
+            .push((self.nodes[index].priority, Reverse(index), id));
+    }
+
+    /// Mark rule as completed to make ready its dependents
+    fn complete(&mut self, rule: &Rule) {
+        let dependents = self.nodes[&rule.id()].dependents.clone();
+        for dependent in dependents {
+            let index = self.nodes.get_index_of(&dependent).unwrap();
+            let node = &mut self.nodes[index];
+            node.deps -= 1;
+            if node.deps == 0 {
+                self.ready.push((node.priority, Reverse(index), dependent));
+            }
+        }
     }
 }
 
@@ -27,24 +245,148 @@ impl ArtifactStore {
         }
     }
 
-    async fn process_rule<F, R>(rule: Rule, emit: F) -> Result<()>
+    async fn process_rule<F, R>(
+        rule: Rule,
+        slot: usize,
+        store: &ArtifactStore,
+        sandbox: bool,
+        cancel: &Cancel,
+        trace: Option<&Trace>,
+        emit: F,
+    ) -> (Rule, usize, Result<()>)
     where
         F: Fn(RuleStateChange) -> R + Clone,
         R: Future<Output = ()>,
     {
         let emit = emit.clone();
+        let policy = rule.policy();
 
-        Self::notify_rules_state(&emit, once(rule.clone()), RuleState::Processing).await;
-        let result = rule.process().await;
-        Self::notify_rules_state(&emit, once(rule.clone()), RuleState::Processed).await;
-        result
+        let start = Instant::now();
+        let mut attempt = 0;
+        let (result, cancelled) = loop {
+            attempt += 1;
+            emit(RuleStateChange::with_attempt(
+                rule.clone(),
+                RuleState::Processing,
+                attempt,
+            ))
+            .await;
+            let start_time = Time::now();
+            let result = rule.process(store, sandbox, cancel).await;
+            let cancelled = matches!(rule.state(), RuleState::Cancelled);
+            if !matches!(result, Ok(false)) && !cancelled {
+                store
+                    .history
+                    .record(&rule, start_time, Time::now(), result.is_ok());
+            }
+            if result.is_ok() || cancelled || attempt > policy.retries {
+                break (result, cancelled);
+            }
+            let delay = policy.delay(attempt);
+            if let Err(error) = &result {
+                log::warn!(
+                    "{} failed on attempt {} due to: {}. Retrying after {:?}",
+                    rule,
+                    attempt,
+                    error,
+                    delay
+                );
+            }
+            if cancel.run(async_std::task::sleep(delay)).await.is_err() {
+                break (result, cancelled);
+            }
+        };
+        let result = result.map(|_| ());
+        if let Some(trace) = trace {
+            trace.add_rule(&rule, slot, start, Instant::now(), result.is_ok());
+        }
+        Self::notify_rules_state(
+            &emit,
+            once(rule.clone()),
+            if cancelled {
+                RuleState::Cancelled
+            } else {
+                RuleState::Processed
+            },
+        )
+        .await;
+        (rule, slot, result)
+    }
+
+    /// Append the goals which was not built to error message
+    fn write_unbuilt<K>(message: &mut String, goals: &[Artifact<(), K>]) {
+        let unbuilt = goals
+            .iter()
+            .filter(|goal| !matches!(goal.state(), RuleState::Processed))
+            .map(|goal| format!("`{}`", goal.name()))
+            .collect::<Vec<_>>();
+        if !unbuilt.is_empty() {
+            let _ = write!(message, "\nUnbuilt goals: {}", unbuilt.join(", "));
+        }
+    }
+
+    fn failure_error<K>(failed: &[(Rule, Error)], goals: &[Artifact<(), K>]) -> Error {
+        let mut message = String::default();
+        if failed.is_empty() {
+            message.push_str("Cannot be built");
+        } else {
+            let _ = write!(message, "{} rule(s) failed:", failed.len());
+            for (rule, error) in failed {
+                let _ = write!(message, "\n    {}: {}", rule.describe(), error);
+            }
+        }
+        Self::write_unbuilt(&mut message, goals);
+        message.into()
+    }
+
+    fn cancel_error<K>(
+        cancelled: &[Rule],
+        failed: &[(Rule, Error)],
+        unstarted: usize,
+        goals: &[Artifact<(), K>],
+    ) -> Error {
+        let mut message = format!(
+            "Cancelled: {} rule(s) interrupted, {} rule(s) failed, {} rule(s) not started",
+            cancelled.len(),
+            failed.len(),
+            unstarted
+        );
+        for rule in cancelled {
+            let _ = write!(message, "\n    {}: cancelled", rule.describe());
+        }
+        for (rule, error) in failed {
+            let _ = write!(message, "\n    {}: {}", rule.describe(), error);
+        }
+        Self::write_unbuilt(&mut message, goals);
+        message.into()
+    }
+
+    /// Restore the discovered inputs of rules which is needed to build goals
+    async fn restore_discovered_inputs<K>(&self, goals: &[Artifact<(), K>]) -> Result<()> {
+        let mut restored = Set::default();
+        // the restored inputs may be produced by rules which is not reached yet
+        loop {
+            let rules = self
+                .goal_rules(goals.iter().cloned())
+                .into_iter()
+                .filter(|rule| !restored.contains(rule))
+                .collect::<Vec<_>>();
+            if rules.is_empty() {
+                return Ok(());
+            }
+            for rule in rules {
+                rule.restore_discovered_inputs(self).await?;
+                restored.insert(rule);
+            }
+        }
     }
 
     async fn process_artifacts<K, I, F, R>(
         &self,
         artifacts: I,
-        jobs: usize,
-        dry_run: bool,
+        opts: &ProcessOpts,
+        cancel: &Cancel,
+        trace: Option<&Trace>,
         emit: F,
     ) -> Result<()>
     where
@@ -52,93 +394,195 @@ impl ArtifactStore {
         F: Fn(RuleStateChange) -> R + Clone,
         R: Future<Output = ()>,
     {
-        let mut queue = VecDeque::new();
+        let &ProcessOpts {
+            jobs,
+            failures,
+            dry_run,
+            sandbox,
+            explain,
+            ..
+        } = opts;
+        let goals = artifacts.collect::<Vec<_>>();
+
+        self.restore_discovered_inputs(&goals).await?;
+
+        let mut visited = Set::default();
+        for goal in &goals {
+            if let Some(cycle) = goal.find_cycle(&mut visited) {
+                log::error!("Dependency cycle detected");
+                return Err(format!(
+                    "Dependency cycle detected while processing goal `{}`: {}",
+                    goal.name(),
+                    Artifact::<Input>::fmt_cycle(&cycle)
+                )
+                .into());
+            }
+        }
+        let mut queue = Vec::new();
         let mut unique = Set::default();
-        let mut schedule = |rule: Rule| {
+        let mut schedule = |rule: Rule, reason: Reason| {
             let id = rule.id();
             if !unique.contains(&id) {
+                if explain {
+                    log::info!("Rebuild {} because {}", rule.describe(), reason);
+                }
                 unique.insert(id);
                 rule.schedule();
-                queue.push_back(rule);
+                queue.push(rule);
             }
         };
-        for artifact in artifacts {
-            artifact.process(&mut schedule);
+        let mut visited = Map::default();
+        for artifact in &goals {
+            artifact
+                .process(&self.database, &mut visited, &mut schedule)
+                .await?;
         }
         if dry_run {
             return Ok(());
         }
 
+        let pools = self.pools.read().clone();
+        if let Some((rule, pool)) = queue.iter().find_map(|rule| {
+            rule.pool()
+                .filter(|pool| !pools.contains_key(pool))
+                .map(|pool| (rule, pool))
+        }) {
+            return Err(format!(
+                "Unknown pool `{}` of rule which produces {}",
+                pool,
+                rule.describe()
+            )
+            .into());
+        }
+        // number of pending rules in each pool
+        let mut usage = Map::<String, usize>::default();
+
         Self::notify_rules_state(&emit, queue.iter().cloned(), RuleState::Scheduled).await;
 
-        log::trace!("Prepare pending");
-        let mut pending_tasks = (0..jobs)
-            .into_iter()
-            .filter_map(|_| {
-                log::trace!("Prepare pending rule");
-                let mut out = 0;
-                while !queue.is_empty() {
-                    if let Some(rule) = queue.pop_front() {
-                        if rule.ready_inputs() {
-                            log::trace!("Add pending rule");
-                            return Some(Box::pin(Self::process_rule(rule, &emit)));
+        let mut graph = RuleGraph::new(queue, &self.database);
+        let mut pending = Vec::new();
+        let mut failed = Vec::new();
+        let mut cancelled = Vec::new();
+        let mut started = 0;
+        // free job slots
+        let mut slots = (0..jobs).map(Reverse).collect::<BinaryHeap<_>>();
+
+        #[cfg(all(unix, feature = "jobserver"))]
+        let jobserver = self.jobserver.read().clone();
+        // tokens which is held by pending rules except first one
+        #[cfg(all(unix, feature = "jobserver"))]
+        let mut tokens = Vec::new();
+
+        loop {
+            #[cfg(all(unix, feature = "jobserver"))]
+            let mut waiting_token = false;
+
+            while pending.len() < jobs
+                && (failures == 0 || failed.len() < failures)
+                && !cancel.is_cancelled()
+            {
+                let rule = if let Some(rule) = graph.pop_ready(|pool| {
+                    pool.map(|pool| usage.get(pool).copied().unwrap_or_default() < pools[pool])
+                        .unwrap_or(true)
+                }) {
+                    rule
+                } else {
+                    break;
+                };
+                #[cfg(all(unix, feature = "jobserver"))]
+                if let Some(jobserver) = &jobserver {
+                    if pending.len() > tokens.len() {
+                        if let Some(token) = jobserver.try_acquire()? {
+                            tokens.push(token);
                         } else {
-                            log::trace!("Re-queue rule");
-                            queue.push_back(rule);
-                            out += 1;
-                            if out >= queue.len() {
-                                break;
-                            }
+                            graph.push_ready(&rule);
+                            waiting_token = true;
+                            break;
                         }
                     }
                 }
-                None
-            })
-            .collect::<Vec<_>>();
+                if let Some(pool) = rule.pool() {
+                    *usage.entry(pool).or_default() += 1;
+                }
+                log::trace!("Add pending rule");
+                let Reverse(slot) = slots.pop().unwrap();
+                pending.push(Box::pin(Self::process_rule(
+                    rule, slot, self, sandbox, cancel, trace, &emit,
+                )));
+                started += 1;
+            }
+            #[cfg(all(unix, feature = "jobserver"))]
+            tokens.truncate(pending.len().saturating_sub(1));
 
-        while !pending_tasks.is_empty() {
+            if pending.is_empty() {
+                break;
+            }
             log::trace!(
                 "Rules {} queued {} pending",
-                queue.len(),
-                pending_tasks.len()
+                graph.len() - started,
+                pending.len()
             );
-            let (result, _, mut pending) = future::select_all(pending_tasks).await;
-            if let Err(error) = result {
-                log::error!("Rule invoking error: {}", error);
-            }
-            let mut out = 0;
-            while !queue.is_empty() && pending.len() < jobs {
-                log::trace!("Prepare pending rule");
-                if let Some(rule) = queue.pop_front() {
-                    if rule.ready_inputs() {
-                        log::trace!("Add pending rule");
-                        pending.push(Box::pin(Self::process_rule(rule, &emit)));
-                    } else {
-                        log::trace!("Re-queue rule");
-                        queue.push_back(rule);
-                        out += 1;
-                        if out >= queue.len() {
-                            break;
+            let completion = future::select_all(pending);
+            #[cfg(all(unix, feature = "jobserver"))]
+            let completion = match (&jobserver, waiting_token) {
+                (Some(jobserver), true) => {
+                    match future::select(completion, Box::pin(jobserver.acquire())).await {
+                        future::Either::Left((completion, _)) => completion,
+                        future::Either::Right((token, completion)) => {
+                            tokens.push(token?);
+                            pending = completion.into_inner();
+                            continue;
                         }
                     }
                 }
+                _ => completion.await,
+            };
+            #[cfg(not(all(unix, feature = "jobserver")))]
+            let completion = completion.await;
+            let ((rule, slot, result), _, rest) = completion;
+            pending = rest;
+            slots.push(Reverse(slot));
+            if let Some(pool) = rule.pool() {
+                usage[&pool] -= 1;
+            }
+            match result {
+                Ok(_) => graph.complete(&rule),
+                Err(_) if matches!(rule.state(), RuleState::Cancelled) => cancelled.push(rule),
+                Err(error) => {
+                    log::error!("Rule invoking error: {}", error);
+                    failed.push((rule, error));
+                    if failures > 0 && failed.len() >= failures {
+                        log::warn!(
+                            "Stop scheduling rules due to {} failure(s). Waiting for {} pending rule(s).",
+                            failed.len(),
+                            pending.len()
+                        );
+                    }
+                }
             }
-            pending_tasks = pending;
         }
 
-        if queue.is_empty() {
+        if cancel.is_cancelled() {
+            log::warn!("Processing cancelled");
+            Err(Self::cancel_error(
+                &cancelled,
+                &failed,
+                graph.len() - started,
+                &goals,
+            ))
+        } else if started == graph.len() && failed.is_empty() {
             Ok(())
         } else {
-            log::warn!("Rules {} queued", queue.len());
-            Err(format!("Cannot be built").into())
+            log::warn!("Rules {} queued", graph.len() - started);
+            Err(Self::failure_error(&failed, &goals))
         }
     }
 
     pub async fn process<S, I, F, R>(
         &self,
         goals: I,
-        jobs: usize,
-        dry_run: bool,
+        opts: ProcessOpts,
+        cancel: &Cancel,
         emit: F,
     ) -> Result<()>
     where
@@ -148,15 +592,30 @@ impl ArtifactStore {
         R: Future<Output = ()>,
     {
         log::debug!("Process artifacts");
-        self.process_artifacts(
-            goals
-                .into_iter()
-                .filter_map(|name| self.phony.read().get(name.as_ref())),
-            jobs,
-            dry_run,
-            emit,
-        )
-        .await?;
+        let trace = opts.trace.as_ref().map(|_| Trace::default());
+        self.history.begin();
+        let result = self
+            .process_artifacts(
+                goals
+                    .into_iter()
+                    .filter_map(|name| self.phony.read().get(name.as_ref())),
+                &opts,
+                cancel,
+                trace.as_ref(),
+                emit,
+            )
+            .await;
+
+        self.database.save().await?;
+        self.history.save().await?;
+        self.cache.evict().await?;
+        if self.cache.is_enabled() {
+            log::debug!("Build cache: {}", self.cache.stats());
+        }
+        if let (Some(trace), Some(path)) = (&trace, &opts.trace) {
+            trace.save(path).await?;
+        }
+        result?;
 
         self.remove_expired();
 
@@ -196,3 +655,511 @@ impl ArtifactStore {
         self.phony.write().remove_expired();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        system::{write_file, Path},
+        Actual, BoxedFuture, Diagnostics, Invocation, Mut, NoRule, Output, Phony, Ref, RuleApi,
+        RulePolicy, RuleSignature, WeakArtifact,
+    };
+    use futures::future::FutureExt;
+
+    type Log = Ref<Mut<Vec<String>>>;
+
+    /// The rule which writes its output after delay
+    struct TestRule {
+        name: String,
+        inputs: Vec<Artifact<Input>>,
+        implicit_inputs: Vec<Artifact<Input>>,
+        order_inputs: Vec<Artifact<Input>>,
+        output: WeakArtifact<Output>,
+        delay: Duration,
+        /// Number of attempts which fails
+        failures: Ref<Mut<usize>>,
+        /// Write output before failing
+        partial: bool,
+        pool: Option<String>,
+        policy: RulePolicy,
+        log: Log,
+    }
+
+    impl RuleApi for TestRule {
+        fn inputs(&self) -> Vec<Artifact<Input>> {
+            self.inputs.clone()
+        }
+
+        fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+            self.implicit_inputs.clone()
+        }
+
+        fn order_inputs(&self) -> Vec<Artifact<Input>> {
+            self.order_inputs.clone()
+        }
+
+        fn outputs(&self) -> Vec<Artifact<Output>> {
+            self.output.try_ref().into_iter().collect()
+        }
+
+        fn signature(&self) -> RuleSignature {
+            RuleSignature::exec("test", once(&self.name))
+        }
+
+        fn pool(&self) -> Option<String> {
+            self.pool.clone()
+        }
+
+        fn policy(&self) -> RulePolicy {
+            self.policy
+        }
+
+        fn invoke(self: Ref<Self>, _invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+            async move {
+                self.log.write().push(format!("start {}", self.name));
+                async_std::task::sleep(self.delay).await;
+                self.log.write().push(format!("end {}", self.name));
+                let failed = {
+                    let mut failures = self.failures.write();
+                    if *failures > 0 {
+                        *failures -= 1;
+                        true
+                    } else {
+                        false
+                    }
+                };
+                if failed && !self.partial {
+                    return Err(format!("{} failed", self.name).into());
+                }
+                if let Some(output) = self.output.try_ref() {
+                    // the contents is changed on each invoking
+                    let contents = format!("{} {}", self.name, self.log.read().len());
+                    write_file(output.name(), contents).await?;
+                }
+                if failed {
+                    return Err(format!("{} failed", self.name).into());
+                }
+                Ok(Diagnostics::default())
+            }
+            .boxed_local()
+        }
+    }
+
+    /// The build of rules which depends on common source
+    struct Build {
+        dir: tempfile::TempDir,
+        store: ArtifactStore,
+        log: Log,
+        source: Artifact<Input, Actual>,
+        outputs: Map<String, Artifact<Output, Actual>>,
+        goals: Vec<Artifact<Output, Phony>>,
+    }
+
+    impl Build {
+        async fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let store = ArtifactStore::default();
+            let name = dir.path().join("source").display().to_string();
+            write_file(&name, "source").await.unwrap();
+            let source = Artifact::<Input, Actual>::new_init(&store, name, "")
+                .await
+                .unwrap();
+            Self {
+                dir,
+                store,
+                log: Default::default(),
+                source,
+                outputs: Default::default(),
+                goals: Vec::new(),
+            }
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.dir.path().join(name).display().to_string()
+        }
+
+        /// Add rule which produces output using source and another outputs
+        fn rule(&mut self, name: &str, inputs: &[&str], setup: impl FnOnce(&mut TestRule)) -> Rule {
+            let output = Artifact::<Output, Actual>::new(&self.store, self.path(name), "").unwrap();
+            let mut rule = TestRule {
+                name: name.into(),
+                inputs: once(self.source.clone().into_kind_any())
+                    .chain(
+                        inputs
+                            .iter()
+                            .map(|input| self.outputs[*input].input().into_kind_any()),
+                    )
+                    .collect(),
+                implicit_inputs: Vec::new(),
+                order_inputs: Vec::new(),
+                output: output.clone().into_kind_any().weak(),
+                delay: Duration::from_millis(10),
+                failures: Default::default(),
+                partial: false,
+                pool: None,
+                policy: Default::default(),
+                log: self.log.clone(),
+            };
+            setup(&mut rule);
+            let rule = Rule::from_api(Ref::new(rule));
+            output.set_rule(rule.clone()).unwrap();
+            self.outputs.insert(name.into(), output);
+            rule
+        }
+
+        /// Add goal which depends on outputs
+        fn goal(&mut self, name: &str, inputs: &[&str]) {
+            let goal = Artifact::<Output, Phony>::new(&self.store, name, "").unwrap();
+            NoRule::new_raw(
+                inputs
+                    .iter()
+                    .map(|input| self.outputs[*input].input().into_kind_any())
+                    .collect(),
+                once(goal.clone().into_kind_any()).collect(),
+            )
+            .unwrap();
+            self.goals.push(goal);
+        }
+
+        async fn run(&self, opts: ProcessOpts) -> Result<()> {
+            self.store
+                .process(
+                    self.goals.iter().map(|goal| goal.name().clone()),
+                    opts,
+                    &Cancel::default(),
+                    |_| async {},
+                )
+                .await
+        }
+
+        fn log(&self) -> Vec<String> {
+            self.log.read().clone()
+        }
+
+        fn started(&self, name: &str) -> bool {
+            self.log().contains(&format!("start {}", name))
+        }
+    }
+
+    async fn failing_build() -> Build {
+        let mut build = Build::new().await;
+        build.rule("a", &[], |rule| *rule.failures.write() = 1);
+        for name in &["b1", "b2", "b3"] {
+            build.rule(name, &[], |_| ());
+        }
+        build.rule("c", &["a"], |_| ());
+        build.goal("all", &["b1", "b2", "b3", "c"]);
+        build
+    }
+
+    #[async_std::test]
+    async fn fail_fast() {
+        let build = failing_build().await;
+        let error = build
+            .run(ProcessOpts {
+                failures: 1,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("1 rule(s) failed"));
+        assert!(error.contains("Unbuilt goals: `all`"));
+        // no rules is started after failure
+        let log = build.log();
+        let failed = log.iter().position(|entry| entry == "end a").unwrap();
+        assert!(log[failed..]
+            .iter()
+            .all(|entry| !entry.starts_with("start")));
+        assert!(!build.started("c"));
+    }
+
+    #[async_std::test]
+    async fn keep_going() {
+        let build = failing_build().await;
+        let error = build
+            .run(ProcessOpts {
+                failures: 0,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("1 rule(s) failed"));
+        assert!(error.contains(&format!("`{}`:", build.path("a"))));
+        // independent rules is processed but dependents of failed rule is not
+        for name in &["b1", "b2", "b3"] {
+            assert!(build.started(name));
+        }
+        assert!(!build.started("c"));
+    }
+
+    fn set_duration(build: &Build, name: &str, millis: u64) {
+        build.store.database.set_output(
+            build.path(name),
+            crate::OutputEntry {
+                signature: String::default(),
+                time: Time::UNIX_EPOCH,
+                digest: String::default(),
+                duration: Duration::from_millis(millis),
+                inputs: Default::default(),
+                side: Vec::new(),
+                discovered: Vec::new(),
+            },
+        );
+    }
+
+    #[async_std::test]
+    async fn critical_path() {
+        let mut build = Build::new().await;
+        let y = build.rule("y", &[], |_| ());
+        let z = build.rule("z", &[], |_| ());
+        let x1 = build.rule("x1", &[], |_| ());
+        let x2 = build.rule("x2", &["x1"], |_| ());
+        set_duration(&build, "x1", 1000);
+        set_duration(&build, "x2", 5000);
+        set_duration(&build, "y", 3000);
+        // `z` was never processed so average duration is used
+
+        let mut graph = RuleGraph::new(
+            vec![y.clone(), z.clone(), x1.clone(), x2.clone()],
+            &build.store.database,
+        );
+        let mut order = Vec::new();
+        while let Some(rule) = graph.pop_ready(|_| true) {
+            graph.complete(&rule);
+            order.push(rule);
+        }
+        // the short rule which starts the longest path goes first
+        assert_eq!(
+            order.iter().map(Rule::id).collect::<Vec<_>>(),
+            [x1.id(), x2.id(), y.id(), z.id()]
+        );
+    }
+
+    #[async_std::test]
+    async fn dependencies_order() {
+        let mut build = Build::new().await;
+        build.rule("a", &[], |_| ());
+        build.rule("b", &["a"], |_| ());
+        build.rule("c", &["a", "b"], |_| ());
+        build.goal("all", &["c"]);
+        build
+            .run(ProcessOpts {
+                jobs: 4,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            build.log(),
+            ["start a", "end a", "start b", "end b", "start c", "end c"]
+        );
+    }
+
+    #[async_std::test]
+    async fn pool_depth() {
+        let mut build = Build::new().await;
+        build.store.new_pool("link", 1).unwrap();
+        for name in &["l1", "l2", "l3"] {
+            build.rule(name, &[], |rule| rule.pool = Some("link".into()));
+        }
+        build.goal("all", &["l1", "l2", "l3"]);
+        build
+            .run(ProcessOpts {
+                jobs: 3,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        // the rules of pool never overlaps
+        let log = build.log();
+        assert_eq!(log.len(), 6);
+        for pair in log.chunks(2) {
+            assert_eq!(pair[0].replace("start", "end"), pair[1]);
+        }
+    }
+
+    #[async_std::test]
+    async fn unknown_pool() {
+        let mut build = Build::new().await;
+        build.rule("a", &[], |rule| rule.pool = Some("link".into()));
+        build.goal("all", &["a"]);
+        let error = build.run(Default::default()).await.unwrap_err().to_string();
+        assert!(error.contains("Unknown pool `link`"));
+        assert!(!build.started("a"));
+    }
+
+    #[async_std::test]
+    async fn trace() {
+        let build = failing_build().await;
+        let path = build.path("trace.json");
+        build
+            .run(ProcessOpts {
+                failures: 0,
+                trace: Some(path.clone().into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        let raw = crate::system::read_file(&path).await.unwrap();
+        let data: serde_json::Value = serde_json::from_slice(&raw).unwrap();
+        assert_eq!(data["displayTimeUnit"], "ms");
+        let events = data["traceEvents"].as_array().unwrap();
+        let event = |name: &str| {
+            events
+                .iter()
+                .find(|event| event["name"] == build.path(name))
+                .cloned()
+        };
+        for name in &["b1", "b2", "b3"] {
+            let event = event(name).unwrap();
+            assert_eq!(event["ph"], "X");
+            assert_eq!(event["args"]["command"], format!("test {}", name));
+            assert_eq!(event["args"]["success"], true);
+            assert!(event["dur"].as_u64().unwrap() >= 10_000);
+        }
+        assert_eq!(event("a").unwrap()["args"]["success"], false);
+        // dependents of failed rule is not processed
+        assert!(event("c").is_none());
+    }
+
+    #[async_std::test]
+    async fn order_inputs() {
+        let mut build = Build::new().await;
+        build.rule("gen", &[], |_| ());
+        let gen = build.outputs["gen"].input().into_kind_any();
+        build.rule("order", &[], |rule| rule.order_inputs.push(gen.clone()));
+        build.rule("implicit", &[], |rule| {
+            rule.implicit_inputs.push(gen.clone())
+        });
+        build.goal("all", &["order", "implicit"]);
+        build
+            .run(ProcessOpts {
+                jobs: 3,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        // both kinds of inputs is built before
+        let log = build.log();
+        let position = |entry: &str| log.iter().position(|item| item == entry).unwrap();
+        assert!(position("end gen") < position("start order"));
+        assert!(position("end gen") < position("start implicit"));
+
+        // only the rules which tracks changed input is rebuilt
+        let offset = log.len();
+        let mut entry = build.store.database.output(build.path("gen")).unwrap();
+        entry.signature = "changed".into();
+        build.store.database.set_output(build.path("gen"), entry);
+        build.run(Default::default()).await.unwrap();
+        let started = |name: &str| build.log()[offset..].contains(&format!("start {}", name));
+        assert!(started("gen"));
+        assert!(started("implicit"));
+        assert!(!started("order"));
+    }
+
+    #[async_std::test]
+    async fn retries() {
+        let policy = RulePolicy {
+            retries: 2,
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(1));
+        assert_eq!(policy.delay(3), Duration::from_millis(4));
+
+        let mut build = Build::new().await;
+        build.rule("flaky", &[], |rule| {
+            *rule.failures.write() = 2;
+            rule.policy = policy;
+        });
+        build.rule("broken", &[], |rule| {
+            *rule.failures.write() = 3;
+            rule.policy = policy;
+        });
+        build.goal("all", &["flaky", "broken"]);
+        let error = build
+            .run(ProcessOpts {
+                failures: 0,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("1 rule(s) failed"));
+        assert!(error.contains("broken failed"));
+        // the rule is attempted once and retried twice
+        let attempts = |name: &str| {
+            let entry = format!("start {}", name);
+            build.log().iter().filter(|item| **item == entry).count()
+        };
+        assert_eq!(attempts("flaky"), 3);
+        assert_eq!(attempts("broken"), 3);
+        assert!(Path::new(&build.path("flaky")).is_file().await);
+    }
+
+    #[async_std::test]
+    async fn partial_retries() {
+        let failures = Ref::new(Mut::new(0));
+        let mut build = Build::new().await;
+        build.rule("partial", &[], |rule| {
+            rule.failures = failures.clone();
+            rule.partial = true;
+            rule.policy = RulePolicy {
+                retries: 1,
+                retry_delay: Duration::from_millis(1),
+                ..Default::default()
+            };
+        });
+        build.goal("all", &["partial"]);
+        build.run(Default::default()).await.unwrap();
+
+        // the rebuild fails after writing output
+        let offset = build.log().len();
+        let mut entry = build.store.database.output(build.path("partial")).unwrap();
+        entry.signature = "changed".into();
+        build
+            .store
+            .database
+            .set_output(build.path("partial"), entry);
+        *failures.write() = 1;
+        build.run(Default::default()).await.unwrap();
+        // the output of failed attempt is removed so the rule is invoked again
+        assert_eq!(
+            build.log()[offset..],
+            [
+                "start partial",
+                "end partial",
+                "start partial",
+                "end partial"
+            ]
+        );
+        assert!(Path::new(&build.path("partial")).is_file().await);
+        assert!(build.store.database.output(build.path("partial")).is_some());
+    }
+
+    #[async_std::test]
+    async fn timeout() {
+        let mut build = Build::new().await;
+        build.rule("slow", &[], |rule| {
+            rule.delay = Duration::from_secs(30);
+            rule.policy = RulePolicy {
+                timeout: Some(Duration::from_millis(20)),
+                retries: 1,
+                retry_delay: Duration::from_millis(1),
+            };
+        });
+        build.goal("all", &["slow"]);
+        let start = Instant::now();
+        let error = build.run(Default::default()).await.unwrap_err().to_string();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(error.contains("Timed out after"));
+        // each attempt is interrupted without cancelling processing
+        assert_eq!(build.log(), ["start slow", "start slow"]);
+        assert!(!matches!(
+            build.outputs["slow"].rule().unwrap().state(),
+            RuleState::Cancelled
+        ));
+    }
+}
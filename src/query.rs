@@ -0,0 +1,427 @@
+mod parser;
+
+use crate::{
+    Artifact, ArtifactKind, ArtifactStore, ArtifactType, Input, Map, Result, RuleState, Set,
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// The query over artifacts graph
+///
+/// The query language is similar to `bazel query`:
+///
+/// - `name` or `"name"` - artifact by name (`*` in name matches any characters)
+/// - `deps(expr[, depth])` - the dependencies of artifacts including itself
+/// - `rdeps(scope, expr[, depth])` - the artifacts in dependencies of scope which depends on artifacts
+/// - `somepath(from, to)` - some path from artifacts to its dependencies
+/// - `kind(actual|phony, expr)` - filter artifacts by kind
+/// - `type(source|product, expr)` - filter artifacts by type
+/// - `expr + expr`, `expr - expr`, `expr ^ expr` - union, difference and intersection of sets
+///   (also `union`, `except` and `intersect`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Name(String),
+    Deps(Box<Query>, Option<usize>),
+    Rdeps(Box<Query>, Box<Query>, Option<usize>),
+    SomePath(Box<Query>, Box<Query>),
+    Kind(ArtifactKind, Box<Query>),
+    Type(ArtifactType, Box<Query>),
+    Union(Box<Query>, Box<Query>),
+    Except(Box<Query>, Box<Query>),
+    Intersect(Box<Query>, Box<Query>),
+}
+
+/// The description of artifact in query results
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactInfo {
+    pub name: String,
+    pub description: String,
+    #[serde(rename = "type")]
+    pub type_: ArtifactType,
+    pub kind: ArtifactKind,
+    /// The state of rule which produces artifact
+    pub state: Option<RuleState>,
+    /// The direct dependencies of artifact
+    pub inputs: Vec<String>,
+}
+
+impl<U, K> From<&Artifact<U, K>> for ArtifactInfo {
+    fn from(artifact: &Artifact<U, K>) -> Self {
+        Self {
+            name: artifact.name().clone(),
+            description: artifact.description().clone(),
+            type_: artifact.type_(),
+            kind: artifact.kind(),
+            state: artifact.rule().map(|rule| rule.state()),
+            inputs: artifact
+                .inputs()
+                .map(|input| input.name().clone())
+                .collect(),
+        }
+    }
+}
+
+type Artifacts = Set<Artifact<Input>>;
+
+impl Query {
+    /// Evaluate query using artifacts from store
+    pub fn eval(&self, store: &ArtifactStore) -> Result<Artifacts> {
+        Ok(match self {
+            Self::Name(name) => store.find(name)?,
+            Self::Deps(query, depth) => closure(query.eval(store)?, *depth, |artifact| {
+                artifact.inputs().collect()
+            }),
+            Self::Rdeps(scope, query, depth) => {
+                let scope = closure(scope.eval(store)?, None, |artifact| {
+                    artifact.inputs().collect()
+                });
+                let mut users = Map::<Artifact<Input>, Vec<Artifact<Input>>>::default();
+                for artifact in &scope {
+                    for input in artifact.inputs() {
+                        users.entry(input).or_default().push(artifact.clone());
+                    }
+                }
+                let roots = query
+                    .eval(store)?
+                    .into_iter()
+                    .filter(|artifact| scope.contains(artifact))
+                    .collect();
+                closure(roots, *depth, |artifact| {
+                    users.get(artifact).cloned().unwrap_or_default()
+                })
+            }
+            Self::SomePath(from, to) => some_path(from.eval(store)?, &to.eval(store)?),
+            Self::Kind(kind, query) => query
+                .eval(store)?
+                .into_iter()
+                .filter(|artifact| artifact.kind() == *kind)
+                .collect(),
+            Self::Type(type_, query) => query
+                .eval(store)?
+                .into_iter()
+                .filter(|artifact| artifact.type_() == *type_)
+                .collect(),
+            Self::Union(left, right) => {
+                let mut artifacts = left.eval(store)?;
+                artifacts.extend(right.eval(store)?);
+                artifacts
+            }
+            Self::Except(left, right) => {
+                let right = right.eval(store)?;
+                left.eval(store)?
+                    .into_iter()
+                    .filter(|artifact| !right.contains(artifact))
+                    .collect()
+            }
+            Self::Intersect(left, right) => {
+                let right = right.eval(store)?;
+                left.eval(store)?
+                    .into_iter()
+                    .filter(|artifact| right.contains(artifact))
+                    .collect()
+            }
+        })
+    }
+}
+
+impl ArtifactStore {
+    /// Get all known artifacts
+    fn artifacts(&self) -> Vec<Artifact<Input>> {
+        self.phony
+            .read()
+            .iter()
+            .map(|artifact| artifact.into_kind_any().into_usage::<Input>().unwrap())
+            .chain(
+                self.actual
+                    .read()
+                    .iter()
+                    .map(|artifact| artifact.into_kind_any().into_usage::<Input>().unwrap()),
+            )
+            .collect()
+    }
+
+    /// Find artifacts by name or wildcard pattern
+    fn find(&self, pattern: &str) -> Result<Artifacts> {
+        if pattern.contains('*') {
+            return Ok(self
+                .artifacts()
+                .into_iter()
+                .filter(|artifact| wildcard_match(pattern, artifact.name()))
+                .collect());
+        }
+        let artifact = if let Some(artifact) = self.phony.read().get(pattern) {
+            artifact.into_kind_any()
+        } else if let Some(artifact) = self.actual.read().get(pattern) {
+            artifact.into_kind_any()
+        } else {
+            return Err(format!("Unknown artifact `{}`", pattern).into());
+        };
+        let mut artifacts = Artifacts::default();
+        artifacts.insert(artifact.into_usage::<Input>().unwrap());
+        Ok(artifacts)
+    }
+}
+
+/// Collect artifacts which is reachable from roots up to depth
+fn closure(
+    roots: Artifacts,
+    depth: Option<usize>,
+    next: impl Fn(&Artifact<Input>) -> Vec<Artifact<Input>>,
+) -> Artifacts {
+    let mut visited = Artifacts::default();
+    let mut queue = roots
+        .into_iter()
+        .map(|artifact| (artifact, 0))
+        .collect::<VecDeque<_>>();
+    while let Some((artifact, level)) = queue.pop_front() {
+        if visited.contains(&artifact) {
+            continue;
+        }
+        if depth.map(|depth| level < depth).unwrap_or(true) {
+            queue.extend(next(&artifact).into_iter().map(|dep| (dep, level + 1)));
+        }
+        visited.insert(artifact);
+    }
+    visited
+}
+
+/// Find the shortest path from some artifact to some of its dependencies
+///
+/// Returns empty set when no path found.
+fn some_path(from: Artifacts, to: &Artifacts) -> Artifacts {
+    let mut parents = Map::<Artifact<Input>, Option<Artifact<Input>>>::default();
+    let mut queue = VecDeque::new();
+    for artifact in from {
+        if !parents.contains_key(&artifact) {
+            parents.insert(artifact.clone(), None);
+            queue.push_back(artifact);
+        }
+    }
+    while let Some(artifact) = queue.pop_front() {
+        if to.contains(&artifact) {
+            let mut path = vec![artifact];
+            while let Some(Some(parent)) = parents.get(path.last().unwrap()) {
+                path.push(parent.clone());
+            }
+            return path.into_iter().rev().collect();
+        }
+        for input in artifact.inputs() {
+            if !parents.contains_key(&input) {
+                parents.insert(input.clone(), Some(artifact.clone()));
+                queue.push_back(input);
+            }
+        }
+    }
+    Artifacts::default()
+}
+
+/// Match name using pattern where `*` matches any characters
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    if parts.len() < 2 {
+        return pattern == name;
+    }
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    if name.len() < first.len() + last.len() || !name.starts_with(first) || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        if let Some(pos) = rest.find(part) {
+            rest = &rest[pos + part.len()..];
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Actual, NoRule, Output, Phony};
+
+    /// The artifacts should be kept alive while store is used
+    struct Graph {
+        store: ArtifactStore,
+        _artifacts: Vec<Artifact<Input>>,
+    }
+
+    impl Graph {
+        fn eval(&self, query: &str) -> Result<Vec<String>> {
+            Ok(query
+                .parse::<Query>()?
+                .eval(&self.store)?
+                .into_iter()
+                .map(|artifact| artifact.name().clone())
+                .collect())
+        }
+
+        fn names(&self, query: &str) -> Vec<String> {
+            let mut names = self.eval(query).unwrap();
+            names.sort();
+            names
+        }
+    }
+
+    fn produce<K>(output: &Artifact<Output, K>, inputs: &[&Artifact<Input>]) {
+        NoRule::new_raw(
+            inputs.iter().map(|input| (*input).clone()).collect(),
+            std::iter::once(output.clone().into_kind_any()).collect(),
+        )
+        .unwrap();
+    }
+
+    /// `all` -> `main` -> `main.o`, `util.o` -> `main.c`, `util.c`, `config.h`
+    /// `test` -> `util.o`
+    fn graph() -> Graph {
+        let store = ArtifactStore::default();
+        let source = |name: &str| {
+            Artifact::<Input, Actual>::new(&store, name, "")
+                .unwrap()
+                .into_kind_any()
+        };
+        let product = |name: &str| Artifact::<Output, Actual>::new(&store, name, "").unwrap();
+        let goal = |name: &str| Artifact::<Output, Phony>::new(&store, name, "").unwrap();
+
+        let main_c = source("main.c");
+        let util_c = source("util.c");
+        let config_h = source("config.h");
+        let main_o = product("main.o");
+        produce(&main_o, &[&main_c, &config_h]);
+        let util_o = product("util.o");
+        produce(&util_o, &[&util_c, &config_h]);
+        let main = product("main");
+        let objects = [
+            main_o.input().into_kind_any(),
+            util_o.input().into_kind_any(),
+        ];
+        produce(&main, &[&objects[0], &objects[1]]);
+        let all = goal("all");
+        produce(&all, &[&main.input().into_kind_any()]);
+        let test = goal("test");
+        produce(&test, &[&objects[1]]);
+
+        let artifacts = vec![
+            main_c,
+            util_c,
+            config_h,
+            objects[0].clone(),
+            objects[1].clone(),
+            main.input().into_kind_any(),
+            all.input().into_kind_any(),
+            test.input().into_kind_any(),
+        ];
+        Graph {
+            store,
+            _artifacts: artifacts,
+        }
+    }
+
+    #[test]
+    fn deps() {
+        let graph = graph();
+        assert_eq!(
+            graph.names("deps(all)"),
+            ["all", "config.h", "main", "main.c", "main.o", "util.c", "util.o"]
+        );
+        assert_eq!(graph.names("deps(all, 1)"), ["all", "main"]);
+        assert_eq!(graph.names("deps(test, 0)"), ["test"]);
+        assert_eq!(
+            graph.names("deps(test)"),
+            ["config.h", "test", "util.c", "util.o"]
+        );
+    }
+
+    #[test]
+    fn rdeps() {
+        let graph = graph();
+        assert_eq!(
+            graph.names("rdeps(all, config.h)"),
+            ["all", "config.h", "main", "main.o", "util.o"]
+        );
+        assert_eq!(
+            graph.names("rdeps(all, config.h, 1)"),
+            ["config.h", "main.o", "util.o"]
+        );
+        assert_eq!(
+            graph.names("rdeps(test, config.h)"),
+            ["config.h", "test", "util.o"]
+        );
+        // the artifacts out of scope is ignored
+        assert!(graph.names("rdeps(test, main.c)").is_empty());
+    }
+
+    #[test]
+    fn some_path() {
+        let graph = graph();
+        assert_eq!(
+            graph.eval("somepath(all, util.c)").unwrap(),
+            ["all", "main", "util.o", "util.c"]
+        );
+        assert_eq!(graph.eval("somepath(all, all)").unwrap(), ["all"]);
+        assert!(graph.eval("somepath(test, main.c)").unwrap().is_empty());
+    }
+
+    #[test]
+    fn filters() {
+        let graph = graph();
+        assert_eq!(graph.names("kind(phony, *)"), ["all", "test"]);
+        assert_eq!(
+            graph.names("kind(actual, deps(test))"),
+            ["config.h", "util.c", "util.o"]
+        );
+        assert_eq!(
+            graph.names("type(source, deps(all))"),
+            ["config.h", "main.c", "util.c"]
+        );
+        assert_eq!(graph.names("type(product, *.o)"), ["main.o", "util.o"]);
+    }
+
+    #[test]
+    fn operators() {
+        let graph = graph();
+        assert_eq!(
+            graph.names("deps(all) - deps(test)"),
+            ["all", "main", "main.c", "main.o"]
+        );
+        assert_eq!(
+            graph.names("deps(all) intersect deps(test)"),
+            ["config.h", "util.c", "util.o"]
+        );
+        assert_eq!(
+            graph.names("main.c union(util.c)+\"config.h\""),
+            ["config.h", "main.c", "util.c"]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let graph = graph();
+        let error = graph.eval("missing.c").unwrap_err().to_string();
+        assert!(error.contains("Unknown artifact `missing.c`"));
+        let error = graph
+            .eval("deps(all) + deps(missing)")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown artifact `missing`"));
+        let error = graph.eval("depends(all)").unwrap_err().to_string();
+        assert!(error.contains("Unknown function `depends`"));
+        // the pattern which matches nothing is not an error
+        assert!(graph.eval("*.rs").unwrap().is_empty());
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*.c", "main.c"));
+        assert!(wildcard_match("m*n.*", "main.c"));
+        assert!(wildcard_match("src/*/*.c", "src/lib/util.c"));
+        assert!(wildcard_match("main.c", "main.c"));
+        assert!(!wildcard_match("*.c", "main.o"));
+        assert!(!wildcard_match("a*a", "a"));
+        assert!(!wildcard_match("*lib*", "main.c"));
+        assert!(!wildcard_match("main", "main.c"));
+    }
+}
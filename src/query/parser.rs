@@ -0,0 +1,306 @@
+use super::Query;
+use crate::{ArtifactKind, ArtifactType, Error, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::{is_not, tag},
+    character::complete::{char, digit1, multispace0},
+    combinator::{all_consuming, cut, map, map_res, not, opt, value},
+    error::Error as IError,
+    multi::many0,
+    sequence::{delimited, preceded, terminated, tuple},
+    Err as IErr, IResult, Parser,
+};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+    Union,
+    Except,
+    Intersect,
+}
+
+impl Operator {
+    fn apply(self, left: Query, right: Query) -> Query {
+        let (left, right) = (Box::new(left), Box::new(right));
+        match self {
+            Self::Union => Query::Union(left, right),
+            Self::Except => Query::Except(left, right),
+            Self::Intersect => Query::Intersect(left, right),
+        }
+    }
+
+    fn parse(input: &str) -> IResult<&str, Self> {
+        alt((
+            value(Self::Union, alt((tag("+"), keyword("union")))),
+            value(Self::Except, alt((tag("-"), keyword("except")))),
+            value(Self::Intersect, alt((tag("^"), keyword("intersect")))),
+        ))(input)
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Ok(
+            all_consuming(delimited(multispace0, Self::parse, multispace0))(input)
+                .map_err(|error| match error {
+                    IErr::Error(error) => error.input,
+                    IErr::Failure(error) => error.input,
+                    _ => unreachable!(),
+                })
+                .map_err(|rest| {
+                    let parsed = &input[..input.len() - rest.len()];
+                    // the name which is immediately followed by arguments is treated as function
+                    match parsed.rsplit(|c| NAME_DELIMITERS.contains(c)).next() {
+                        Some(name) if rest.starts_with('(') && !name.is_empty() => {
+                            format!("Unknown function `{}`", name)
+                        }
+                        _ => format!("Error while parsing query: `{}`", rest),
+                    }
+                })?
+                .1,
+        )
+    }
+}
+
+impl Query {
+    fn parse(input: &str) -> IResult<&str, Self> {
+        map(
+            tuple((
+                Self::parse_term,
+                many0(tuple((
+                    delimited(multispace0, Operator::parse, multispace0),
+                    Self::parse_term,
+                ))),
+            )),
+            |(first, rest)| {
+                rest.into_iter()
+                    .fold(first, |left, (operator, right)| operator.apply(left, right))
+            },
+        )(input)
+    }
+
+    fn parse_term(input: &str) -> IResult<&str, Self> {
+        alt((
+            Self::parse_func,
+            delimited(
+                tuple((char('('), multispace0)),
+                Self::parse,
+                tuple((multispace0, char(')'))),
+            ),
+            map(parse_name, Self::Name),
+        ))(input)
+    }
+
+    fn parse_func(input: &str) -> IResult<&str, Self> {
+        alt((
+            map(
+                call(
+                    "deps",
+                    tuple((Self::parse, opt(preceded(comma, parse_depth)))),
+                ),
+                |(query, depth)| Self::Deps(Box::new(query), depth),
+            ),
+            map(
+                call(
+                    "rdeps",
+                    tuple((
+                        Self::parse,
+                        comma,
+                        Self::parse,
+                        opt(preceded(comma, parse_depth)),
+                    )),
+                ),
+                |(scope, _, query, depth)| Self::Rdeps(Box::new(scope), Box::new(query), depth),
+            ),
+            map(
+                call("somepath", tuple((Self::parse, comma, Self::parse))),
+                |(from, _, to)| Self::SomePath(Box::new(from), Box::new(to)),
+            ),
+            map(
+                call("kind", tuple((parse_kind, comma, Self::parse))),
+                |(kind, _, query)| Self::Kind(kind, Box::new(query)),
+            ),
+            map(
+                call("type", tuple((parse_type, comma, Self::parse))),
+                |(type_, _, query)| Self::Type(type_, Box::new(query)),
+            ),
+        ))(input)
+    }
+}
+
+/// The characters which cannot be used in unquoted names
+const NAME_DELIMITERS: &str = " \t\r\n(),\"";
+
+/// Parse function call with arguments
+///
+/// The arguments is required when function name is followed by opening parenthesis.
+fn call<'i, O>(
+    name: &'static str,
+    args: impl Parser<&'i str, O, IError<&'i str>>,
+) -> impl FnMut(&'i str) -> IResult<&'i str, O> {
+    preceded(
+        tuple((tag(name), multispace0, char('('), multispace0)),
+        cut(terminated(args, tuple((multispace0, char(')'))))),
+    )
+}
+
+/// Parse keyword which is not a part of name
+fn keyword<'i>(word: &'static str) -> impl FnMut(&'i str) -> IResult<&'i str, &'i str> {
+    terminated(tag(word), not(is_not(NAME_DELIMITERS)))
+}
+
+fn comma(input: &str) -> IResult<&str, char> {
+    delimited(multispace0, char(','), multispace0)(input)
+}
+
+fn parse_name(input: &str) -> IResult<&str, String> {
+    map(
+        alt((
+            delimited(char('"'), is_not("\""), char('"')),
+            is_not(NAME_DELIMITERS),
+        )),
+        String::from,
+    )(input)
+}
+
+fn parse_depth(input: &str) -> IResult<&str, usize> {
+    map_res(digit1, usize::from_str)(input)
+}
+
+fn parse_kind(input: &str) -> IResult<&str, ArtifactKind> {
+    alt((
+        value(ArtifactKind::Actual, tag("actual")),
+        value(ArtifactKind::Phony, tag("phony")),
+    ))(input)
+}
+
+fn parse_type(input: &str) -> IResult<&str, ArtifactType> {
+    alt((
+        value(ArtifactType::Source, tag("source")),
+        value(ArtifactType::Product, tag("product")),
+    ))(input)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn name(name: &str) -> Box<Query> {
+        Box::new(Query::Name(name.into()))
+    }
+
+    #[test]
+    fn names() {
+        let query: Query = "all".parse().unwrap();
+        assert_eq!(query, Query::Name("all".into()));
+
+        let query: Query = " src/hello-world.c ".parse().unwrap();
+        assert_eq!(query, Query::Name("src/hello-world.c".into()));
+
+        let query: Query = r#""dir with spaces/main.c""#.parse().unwrap();
+        assert_eq!(query, Query::Name("dir with spaces/main.c".into()));
+
+        let query: Query = "target/*.o".parse().unwrap();
+        assert_eq!(query, Query::Name("target/*.o".into()));
+    }
+
+    #[test]
+    fn functions() {
+        let query: Query = "deps(all)".parse().unwrap();
+        assert_eq!(query, Query::Deps(name("all"), None));
+
+        let query: Query = "deps ( all , 2 )".parse().unwrap();
+        assert_eq!(query, Query::Deps(name("all"), Some(2)));
+
+        let query: Query = "rdeps(all, src/hello.h)".parse().unwrap();
+        assert_eq!(query, Query::Rdeps(name("all"), name("src/hello.h"), None));
+
+        let query: Query = "rdeps(all, src/hello.h, 1)".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Rdeps(name("all"), name("src/hello.h"), Some(1))
+        );
+
+        let query: Query = "somepath(hello,src/hello.h)".parse().unwrap();
+        assert_eq!(query, Query::SomePath(name("hello"), name("src/hello.h")));
+
+        let query: Query = "kind(phony, *)".parse().unwrap();
+        assert_eq!(query, Query::Kind(ArtifactKind::Phony, name("*")));
+
+        let query: Query = "type(source, deps(all))".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Type(
+                ArtifactType::Source,
+                Box::new(Query::Deps(name("all"), None))
+            )
+        );
+    }
+
+    #[test]
+    fn function_names() {
+        let query: Query = "deps".parse().unwrap();
+        assert_eq!(query, Query::Name("deps".into()));
+
+        let query: Query = "types.h".parse().unwrap();
+        assert_eq!(query, Query::Name("types.h".into()));
+    }
+
+    #[test]
+    fn operators() {
+        let query: Query = "deps(all) - deps(test)".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Except(
+                Box::new(Query::Deps(name("all"), None)),
+                Box::new(Query::Deps(name("test"), None))
+            )
+        );
+
+        let query: Query = "a + b ^ c".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Intersect(Box::new(Query::Union(name("a"), name("b"))), name("c"))
+        );
+
+        let query: Query = "a union (b intersect c)".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Union(name("a"), Box::new(Query::Intersect(name("b"), name("c"))))
+        );
+
+        let query: Query = "hello-world except hello".parse().unwrap();
+        assert_eq!(query, Query::Except(name("hello-world"), name("hello")));
+
+        let query: Query = "a union(b)intersect(c)".parse().unwrap();
+        assert_eq!(
+            query,
+            Query::Intersect(Box::new(Query::Union(name("a"), name("b"))), name("c"))
+        );
+
+        // keyword should not be a part of name
+        assert!("a unions b".parse::<Query>().is_err());
+        let query: Query = "union.h - a".parse().unwrap();
+        assert_eq!(query, Query::Except(name("union.h"), name("a")));
+    }
+
+    #[test]
+    fn errors() {
+        assert!("deps(all".parse::<Query>().is_err());
+        assert!("kind(unknown, all)".parse::<Query>().is_err());
+        assert!("a +".parse::<Query>().is_err());
+        assert!("".parse::<Query>().is_err());
+
+        let error = "foo(all)".parse::<Query>().unwrap_err().to_string();
+        assert!(error.contains("Unknown function `foo`"));
+        let error = "deps(bar(all), 1)"
+            .parse::<Query>()
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Unknown function `bar`"));
+        let error = "deps(all, x)".parse::<Query>().unwrap_err().to_string();
+        assert!(error.contains("Error while parsing query"));
+    }
+}
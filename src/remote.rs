@@ -0,0 +1,531 @@
+use crate::{
+    system::{access, create_dir_all, read_file, write_file, AccessMode, Path},
+    Map, Ref, Result,
+};
+use sha2::{Digest, Sha256};
+use std::{fmt::Write, str::from_utf8};
+use surf::{Body, Client, StatusCode, Url};
+
+/// The file which is stored in remote cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteFile {
+    /// The path of file
+    pub path: String,
+    /// The SHA-256 digest of contents in hex
+    pub hash: String,
+    /// The size of contents in bytes
+    pub size: u64,
+    /// The file is executable
+    pub executable: bool,
+}
+
+/// The action result which is stored in remote cache
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteEntry {
+    /// The output files of rule
+    pub files: Vec<RemoteFile>,
+    /// The digests of inputs which was discovered by rule
+    pub discovered: Map<String, String>,
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_uint(out: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(out, (field as u64) << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u32, data: &[u8]) {
+    put_varint(out, ((field as u64) << 3) | 2);
+    put_varint(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+/// The value of protobuf field
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// The reader of protobuf message fields
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self
+                .0
+                .split_first()
+                .ok_or("Unexpected end of protobuf message")?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Too long protobuf varint".into())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err("Unexpected end of protobuf message".into());
+        }
+        let (data, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    fn next(&mut self) -> Result<Option<(u32, Value<'a>)>> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        let value = match tag & 7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            wire => return Err(format!("Unsupported protobuf wire type {}", wire).into()),
+        };
+        Ok(Some(((tag >> 3) as u32, value)))
+    }
+}
+
+impl RemoteFile {
+    /// Encode as `OutputFile` message
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut digest = Vec::new();
+        put_bytes(&mut digest, 1, self.hash.as_bytes());
+        put_uint(&mut digest, 2, self.size);
+        put_bytes(out, 1, self.path.as_bytes());
+        put_bytes(out, 2, &digest);
+        if self.executable {
+            put_uint(out, 4, 1);
+        }
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        let mut path = None;
+        let mut hash = None;
+        let mut size = 0;
+        let mut executable = false;
+        let mut fields = Fields(data);
+        while let Some(field) = fields.next()? {
+            match field {
+                (1, Value::Bytes(data)) => path = Some(from_utf8(data)?.to_string()),
+                (2, Value::Bytes(data)) => {
+                    let mut fields = Fields(data);
+                    while let Some(field) = fields.next()? {
+                        match field {
+                            (1, Value::Bytes(data)) => hash = Some(from_utf8(data)?.to_string()),
+                            (2, Value::Varint(value)) => size = value,
+                            _ => (),
+                        }
+                    }
+                }
+                (4, Value::Varint(value)) => executable = value != 0,
+                _ => (),
+            }
+        }
+        if let (Some(path), Some(hash)) = (path, hash) {
+            Ok(Self {
+                path,
+                hash,
+                size,
+                executable,
+            })
+        } else {
+            Err("Output file of action result has no path or digest".into())
+        }
+    }
+}
+
+impl RemoteEntry {
+    /// Encode as `ActionResult` message
+    ///
+    /// The digests of discovered inputs is kept as JSON in `stdout_raw` field.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for file in &self.files {
+            let mut data = Vec::new();
+            file.encode(&mut data);
+            put_bytes(&mut out, 2, &data);
+        }
+        if !self.discovered.is_empty() {
+            put_bytes(&mut out, 5, &serde_json::to_vec(&self.discovered)?);
+        }
+        Ok(out)
+    }
+
+    /// Decode from `ActionResult` message
+    ///
+    /// The fields which is not used by gear will be ignored.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut entry = Self::default();
+        let mut fields = Fields(data);
+        while let Some(field) = fields.next()? {
+            match field {
+                (2, Value::Bytes(data)) => entry.files.push(RemoteFile::decode(data)?),
+                (5, Value::Bytes(data)) if !data.is_empty() => {
+                    entry.discovered = serde_json::from_slice(data)?
+                }
+                _ => (),
+            }
+        }
+        Ok(entry)
+    }
+}
+
+pub struct Internal {
+    url: Url,
+    client: Client,
+    upload: bool,
+}
+
+/// The remote build cache which speaks Bazel HTTP cache protocol
+///
+/// Action results are stored under `/ac/` and file contents under `/cas/` using SHA-256 digests.
+/// The action results is encoded as `ActionResult` messages of remote execution API.
+#[derive(Clone)]
+pub struct RemoteCache(Ref<Internal>);
+
+fn to_hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+impl RemoteCache {
+    /// Create remote cache client using base URL of cache server
+    ///
+    /// Nothing will be uploaded when `upload` is not set.
+    pub fn new(url: impl AsRef<str>, upload: bool) -> Result<Self> {
+        let url = url.as_ref();
+        // trailing slash is needed to join paths
+        let url = if url.ends_with('/') {
+            url.to_string()
+        } else {
+            format!("{}/", url)
+        };
+        let url =
+            Url::parse(&url).map_err(|error| format!("Invalid remote cache URL: {}", error))?;
+        log::debug!("Use remote build cache `{}`", url);
+        Ok(Self(Ref::new(Internal {
+            url,
+            client: Client::new(),
+            upload,
+        })))
+    }
+
+    fn url(&self, kind: &str, hash: &str) -> Result<Url> {
+        Ok(self
+            .0
+            .url
+            .join(&format!("{}/{}", kind, hash))
+            .map_err(|error| format!("Invalid remote cache URL: {}", error))?)
+    }
+
+    /// Get the action key using the key of local cache entry
+    fn action_key(key: &str) -> String {
+        to_hex(&Sha256::digest(key.as_bytes()))
+    }
+
+    async fn get(&self, kind: &str, hash: &str) -> Result<Option<Vec<u8>>> {
+        let url = self.url(kind, hash)?;
+        let mut res = self
+            .0
+            .client
+            .get(&url)
+            .await
+            .map_err(|error| format!("Unable to fetch `{}` due to: {}", url, error))?;
+        match res.status() {
+            StatusCode::Ok => {
+                Ok(Some(res.body_bytes().await.map_err(|error| {
+                    format!("Unable to fetch `{}` due to: {}", url, error)
+                })?))
+            }
+            StatusCode::NotFound => Ok(None),
+            status => Err(format!("Unable to fetch `{}` due to status {}", url, status).into()),
+        }
+    }
+
+    async fn put(&self, kind: &str, hash: &str, data: Vec<u8>) -> Result<()> {
+        let url = self.url(kind, hash)?;
+        let res = self
+            .0
+            .client
+            .put(&url)
+            .body(Body::from_bytes(data))
+            .await
+            .map_err(|error| format!("Unable to upload `{}` due to: {}", url, error))?;
+        if !res.status().is_success() {
+            return Err(
+                format!("Unable to upload `{}` due to status {}", url, res.status()).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Fetch action result
+    pub async fn lookup(&self, key: &str) -> Result<Option<RemoteEntry>> {
+        Ok(
+            if let Some(data) = self.get("ac", &Self::action_key(key)).await? {
+                Some(RemoteEntry::decode(&data)?)
+            } else {
+                None
+            },
+        )
+    }
+
+    /// Fetch files of action result
+    ///
+    /// Nothing will be written when some blob is missing or does not match its digest.
+    /// The entries which has files other than `outputs` is rejected too.
+    pub async fn restore(&self, entry: &RemoteEntry, outputs: &[String]) -> Result<bool> {
+        if let Some(file) = entry
+            .files
+            .iter()
+            .find(|file| !outputs.contains(&file.path))
+        {
+            log::warn!("Remote cache entry has undeclared output `{}`", file.path);
+            return Ok(false);
+        }
+        let mut blobs = Vec::with_capacity(entry.files.len());
+        for file in &entry.files {
+            let data = if let Some(data) = self.get("cas", &file.hash).await? {
+                data
+            } else {
+                log::debug!("Remote cache blob `{}` is missing", file.hash);
+                return Ok(false);
+            };
+            if data.len() as u64 != file.size || to_hex(&Sha256::digest(&data)) != file.hash {
+                log::warn!("Remote cache blob `{}` is corrupted", file.hash);
+                return Ok(false);
+            }
+            blobs.push(data);
+        }
+        for (file, data) in entry.files.iter().zip(blobs) {
+            let path = Path::new(&file.path);
+            if let Some(parent) = path.parent() {
+                if !parent.as_os_str().is_empty() && !parent.is_dir().await {
+                    create_dir_all(parent).await?;
+                }
+            }
+            write_file(path, data).await?;
+            #[cfg(unix)]
+            if file.executable {
+                use std::os::unix::fs::PermissionsExt;
+                let mut perms = path.metadata().await?.permissions();
+                perms.set_mode(perms.mode() | 0o111);
+                async_std::fs::set_permissions(path, perms).await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Upload files and action result
+    ///
+    /// The files which does not exists will be skipped.
+    pub async fn store<'a>(
+        &self,
+        key: &str,
+        files: impl IntoIterator<Item = &'a String>,
+        discovered: Map<String, String>,
+    ) -> Result<()> {
+        if !self.0.upload {
+            return Ok(());
+        }
+        let mut entry = RemoteEntry {
+            files: Vec::new(),
+            discovered,
+        };
+        for name in files {
+            let path = Path::new(name);
+            if !path.is_file().await {
+                continue;
+            }
+            let data = read_file(path).await?;
+            let hash = to_hex(&Sha256::digest(&data));
+            let size = data.len() as u64;
+            self.put("cas", &hash, data).await?;
+            entry.files.push(RemoteFile {
+                path: name.clone(),
+                hash,
+                size,
+                executable: access(path, AccessMode::EXECUTE).await,
+            });
+        }
+        self.put("ac", &Self::action_key(key), entry.encode()?)
+            .await?;
+        log::debug!("Upload remote cache entry `{}`", key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tide::{Request, Response};
+
+    type Blobs = Arc<Mutex<Map<String, Vec<u8>>>>;
+
+    async fn get(req: Request<Blobs>) -> tide::Result {
+        let key = format!("{}/{}", req.param("kind")?, req.param("hash")?);
+        let data = req.state().lock().unwrap().get(&key).cloned();
+        Ok(if let Some(data) = data {
+            Response::builder(200).body(data).build()
+        } else {
+            Response::new(404)
+        })
+    }
+
+    async fn put(mut req: Request<Blobs>) -> tide::Result {
+        let key = format!("{}/{}", req.param("kind")?, req.param("hash")?);
+        let data = req.body_bytes().await?;
+        req.state().lock().unwrap().insert(key, data);
+        Ok(Response::new(200))
+    }
+
+    /// Start in-process HTTP cache server which keeps blobs in memory
+    fn server() -> (String, Blobs) {
+        let blobs = Blobs::default();
+        let mut app = tide::with_state(blobs.clone());
+        app.at("/:kind/:hash").get(get).put(put);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        async_std::task::spawn(app.listen(listener));
+        (url, blobs)
+    }
+
+    #[test]
+    fn encoding() {
+        let mut discovered = Map::default();
+        discovered.insert("main.h".into(), "digest".into());
+        let entry = RemoteEntry {
+            files: vec![RemoteFile {
+                path: "a".into(),
+                hash: "ff".into(),
+                size: 300,
+                executable: true,
+            }],
+            discovered,
+        };
+        let data = entry.encode().unwrap();
+        assert_eq!(
+            &data[..16],
+            &[
+                0x12, 14, // output_files
+                0x0a, 1, b'a', // path
+                0x12, 7, 0x0a, 2, b'f', b'f', 0x10, 0xac, 0x02, // digest
+                0x20, 1, // is_executable
+            ]
+        );
+        // stdout_raw
+        assert_eq!(data[16], 0x2a);
+        assert_eq!(RemoteEntry::decode(&data).unwrap(), entry);
+
+        assert!(RemoteEntry::decode(&data[..10]).is_err());
+        assert!(RemoteEntry::decode(&[0x12, 0]).is_err());
+        assert_eq!(RemoteEntry::decode(&[]).unwrap(), RemoteEntry::default());
+    }
+
+    #[async_std::test]
+    async fn store_restore() {
+        let (url, blobs) = server();
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        write_file(path("main.o"), "object").await.unwrap();
+        write_file(path("main"), "binary").await.unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path("main"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let mut discovered = Map::default();
+        discovered.insert(path("main.h"), "digest".into());
+
+        let remote = RemoteCache::new(&url, true).unwrap();
+        remote
+            .store(
+                "key",
+                &[path("main.o"), path("main"), path("missing")],
+                discovered.clone(),
+            )
+            .await
+            .unwrap();
+
+        // action result is keyed by SHA-256 digest
+        let action = format!("ac/{}", to_hex(&Sha256::digest(b"key")));
+        let entry = RemoteEntry::decode(&blobs.lock().unwrap()[&action]).unwrap();
+        assert_eq!(entry.discovered, discovered);
+        assert_eq!(entry.files.len(), 2);
+        assert_eq!(entry.files[0].hash, to_hex(&Sha256::digest(b"object")));
+        assert_eq!(entry.files[0].size, 6);
+        assert!(blobs
+            .lock()
+            .unwrap()
+            .contains_key(&format!("cas/{}", entry.files[0].hash)));
+        #[cfg(unix)]
+        assert_eq!(
+            entry
+                .files
+                .iter()
+                .map(|file| file.executable)
+                .collect::<Vec<_>>(),
+            [false, true]
+        );
+
+        let remote = RemoteCache::new(&url, false).unwrap();
+        assert!(remote.lookup("other").await.unwrap().is_none());
+        // read-only cache does not upload anything
+        remote
+            .store("other", &[path("main.o")], Map::default())
+            .await
+            .unwrap();
+        assert!(remote.lookup("other").await.unwrap().is_none());
+
+        async_std::fs::remove_file(path("main.o")).await.unwrap();
+        async_std::fs::remove_file(path("main")).await.unwrap();
+        let entry = remote.lookup("key").await.unwrap().unwrap();
+        let outputs = [path("main.o"), path("main")];
+        // the entry which has undeclared files is not restored
+        assert!(!remote.restore(&entry, &outputs[..1]).await.unwrap());
+        assert!(!Path::new(&path("main.o")).exists().await);
+        assert!(remote.restore(&entry, &outputs).await.unwrap());
+        assert_eq!(read_file(path("main.o")).await.unwrap(), b"object");
+        assert_eq!(read_file(path("main")).await.unwrap(), b"binary");
+        #[cfg(unix)]
+        assert!(access(path("main"), AccessMode::EXECUTE).await);
+
+        // corrupted blob prevents restoring of any file
+        async_std::fs::remove_file(path("main.o")).await.unwrap();
+        async_std::fs::remove_file(path("main")).await.unwrap();
+        blobs.lock().unwrap().insert(
+            format!("cas/{}", entry.files[1].hash),
+            b"corrupted".to_vec(),
+        );
+        assert!(!remote.restore(&entry, &outputs).await.unwrap());
+        assert!(!Path::new(&path("main.o")).exists().await);
+        assert!(!Path::new(&path("main")).exists().await);
+    }
+}
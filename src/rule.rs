@@ -1,17 +1,24 @@
 use crate::{
+    compiler::{CompilerConfig, DepKind},
     qjs,
-    system::{create_dir_all, Path},
-    Artifact, BoxedFuture, Diagnostics, Input, Mut, Output, ParallelSend, ParallelSync, Ref,
-    Result, Set, Time, WeakArtifact, WeakSet,
+    system::{
+        create_dir_all, exec_out_in, modified, remove_file, ExecOut, ExitStatus, Path, Sandbox,
+        EXEC_ENVS,
+    },
+    Actual, Artifact, ArtifactStore, BoxedFuture, Cache, Cancel, DataHasher, Database, Diagnostic,
+    Diagnostics, Duration, Input, InputEntry, Location, Map, Mut, Output, OutputEntry,
+    ParallelSend, ParallelSync, Reason, Ref, Result, Set, Severity, Time, WeakArtifact, WeakSet,
 };
 use derive_deref::Deref;
 use either::Either;
 use futures::future::FutureExt;
 use serde::Serialize;
 use std::{
+    ffi::OsStr,
     fmt::{Display, Formatter, Result as FmtResult},
     hash::{Hash, Hasher},
     iter::once,
+    time::Instant,
 };
 
 /// The unique identifier of rule
@@ -25,6 +32,7 @@ pub enum RuleState {
     Processed,
     Scheduled,
     Processing,
+    Cancelled,
 }
 
 impl Default for RuleState {
@@ -39,21 +47,257 @@ impl Display for RuleState {
             RuleState::Processed => "processed",
             RuleState::Scheduled => "scheduled",
             RuleState::Processing => "processing",
+            RuleState::Cancelled => "cancelled",
         }
         .fmt(fmt)
     }
 }
 
+/// The signature of command which is invoked by rule
+///
+/// Any change of signature causes rule to be invoked again.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct RuleSignature {
+    /// Command to execute
+    pub cmd: String,
+    /// Command-line arguments
+    pub args: Vec<String>,
+    /// Environment variables
+    pub envs: Vec<(String, String)>,
+    /// Working directory
+    pub cwd: Option<String>,
+}
+
+impl RuleSignature {
+    /// Create signature of command which is executed via [`exec_out`](crate::system::exec_out)
+    pub fn exec(cmd: impl Into<String>, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            cmd: cmd.into(),
+            args: args.into_iter().map(|arg| arg.into()).collect(),
+            envs: EXEC_ENVS
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            cwd: None,
+        }
+    }
+}
+
+/// The default delay before first retry of failed rule
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The policy of rule invoking
+#[derive(Debug, Clone, Copy)]
+pub struct RulePolicy {
+    /// Wall-clock timeout of each attempt
+    pub timeout: Option<Duration>,
+    /// Number of retries when attempt fails
+    pub retries: usize,
+    /// Delay before first retry which doubles before each next one
+    pub retry_delay: Duration,
+}
+
+impl Default for RulePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 0,
+            retry_delay: RETRY_DELAY,
+        }
+    }
+}
+
+impl RulePolicy {
+    /// Get the delay before retry (numbered from 1)
+    pub fn delay(&self, retry: usize) -> Duration {
+        self.retry_delay * (1u32 << (retry.saturating_sub(1).min(16) as u32))
+    }
+}
+
+impl<'js> qjs::FromJs<'js> for RulePolicy {
+    fn from_js(_ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        let obj: qjs::Object = val.get()?;
+        let timeout = obj
+            .get::<_, Option<u64>>("timeout")?
+            .map(Duration::from_millis);
+        let retries = obj.get::<_, Option<usize>>("retries")?.unwrap_or_default();
+        let retry_delay = obj
+            .get::<_, Option<u64>>("retryDelay")?
+            .map(Duration::from_millis)
+            .unwrap_or(RETRY_DELAY);
+
+        Ok(Self {
+            timeout,
+            retries,
+            retry_delay,
+        })
+    }
+}
+
+/// The options of rule
+#[derive(Debug, Clone, Default)]
+pub struct RuleOptions {
+    /// The name of pool which limits concurrency
+    pub pool: Option<String>,
+    /// The inputs which is tracked but not passed to command
+    pub implicit_inputs: Set<Artifact<Input>>,
+    /// The inputs which should be built before but does not cause rebuilding
+    pub order_inputs: Set<Artifact<Input>>,
+    /// The dependency file which is generated by rule with its format
+    pub depfile: Option<(String, DepKind)>,
+    /// Replace the rules of outputs which is already produced by another rules
+    pub overrides: bool,
+    /// The timeout and retries of invoking
+    pub policy: RulePolicy,
+    /// The user-supplied key which changes causes rule to be invoked again
+    pub signature: Vec<String>,
+    /// The directories of toolchain which should be visible in sandbox
+    pub toolchain: Vec<String>,
+}
+
+impl<'js> qjs::FromJs<'js> for RuleOptions {
+    fn from_js(_ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        let obj: qjs::Object = val.get()?;
+        let pool = obj.get("pool")?;
+        let implicit_inputs = obj
+            .get::<_, Option<Set<Artifact<Input>>>>("implicitInputs")?
+            .unwrap_or_default();
+        let order_inputs = obj
+            .get::<_, Option<Set<Artifact<Input>>>>("orderInputs")?
+            .unwrap_or_default();
+        let depfile = obj
+            .get::<_, Option<String>>("depfile")?
+            .map(|depfile| -> qjs::Result<_> {
+                let kind = obj
+                    .get::<_, Option<DepKind>>("depfileFormat")?
+                    .unwrap_or_default();
+                Ok((depfile, kind))
+            })
+            .transpose()?;
+        let overrides = obj.get::<_, Option<bool>>("override")?.unwrap_or_default();
+        let policy = val.get()?;
+        let signature = obj
+            .get::<_, Option<Either<Vec<String>, String>>>("signature")?
+            .map(|signature| signature.either(|keys| keys, |key| vec![key]))
+            .unwrap_or_default();
+        let toolchain = obj
+            .get::<_, Option<CompilerConfig>>("toolchain")?
+            .map(|config| config.toolchain_dirs())
+            .unwrap_or_default();
+
+        Ok(Self {
+            pool,
+            implicit_inputs,
+            order_inputs,
+            depfile,
+            overrides,
+            policy,
+            signature,
+            toolchain,
+        })
+    }
+}
+
+/// The environment of rule invoking
+#[derive(Debug, Clone, Default)]
+pub struct Invocation {
+    /// The name of rule which is used to report problems
+    pub rule: String,
+    /// The sandbox which is used to execute commands
+    pub sandbox: Option<Sandbox>,
+    /// The token which cancels executed commands
+    pub cancel: Cancel,
+}
+
+impl Invocation {
+    /// Execute command to collect output using sandbox when it is enabled
+    ///
+    /// The accesses to undeclared paths by failed command will be reported.
+    pub async fn exec_out(
+        &self,
+        cmd: impl AsRef<OsStr>,
+        args: &[impl AsRef<OsStr>],
+    ) -> Result<ExecOut<ExitStatus>> {
+        let res = exec_out_in(self.sandbox.as_ref(), Some(&self.cancel), cmd, args).await?;
+        if !res.is_success() {
+            self.report_undeclared(&res.err);
+        }
+        Ok(res)
+    }
+
+    /// Report accesses to undeclared paths using error output of command
+    pub fn report_undeclared(&self, output: &str) {
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.report_undeclared(&self.rule, output);
+        }
+    }
+}
+
 /// The builder interface
 pub trait RuleApi: ParallelSend + ParallelSync {
     /// Get the list of inputs
     fn inputs(&self) -> Vec<Artifact<Input>>;
 
+    /// Get the list of inputs which is tracked but not passed to command
+    fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+        Vec::new()
+    }
+
+    /// Get the list of inputs which should be built before but does not cause rebuilding
+    fn order_inputs(&self) -> Vec<Artifact<Input>> {
+        Vec::new()
+    }
+
     /// Get the list of outputs
     fn outputs(&self) -> Vec<Artifact<Output>>;
 
+    /// Get the signature of command
+    ///
+    /// Any change of signature causes rule to be invoked again.
+    fn signature(&self) -> RuleSignature;
+
+    /// Get the name of pool which limits concurrency
+    fn pool(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the files which is generated in addition to outputs
+    fn side_outputs(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Get the dependency file which is generated by rule with its format
+    fn depfile(&self) -> Option<(String, DepKind)> {
+        None
+    }
+
+    /// Get the inputs which was discovered while processing
+    ///
+    /// Returns `None` when rule does not discover inputs.
+    fn discovered_inputs(&self) -> Option<Vec<Artifact<Input>>> {
+        None
+    }
+
+    /// Set the inputs which was discovered while processing
+    fn set_discovered_inputs(&self, _inputs: Set<Artifact<Input>>) {}
+
+    /// Check that outputs can be restored from build cache instead of invoking
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    /// Get the timeout and retries of invoking
+    fn policy(&self) -> RulePolicy {
+        RulePolicy::default()
+    }
+
+    /// Get the paths which should be visible in sandbox in addition to inputs
+    fn sandbox_paths(&self) -> Vec<String> {
+        Vec::new()
+    }
+
     /// Run rule
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>>;
+    fn invoke(self: Ref<Self>, invocation: Invocation) -> BoxedFuture<Result<Diagnostics>>;
 }
 
 #[derive(Clone)]
@@ -113,19 +357,271 @@ impl Rule {
         *self.0.state.read()
     }
 
+    /// Check that rules cannot produce the same outputs
+    ///
+    /// Any two different rules is conflicting even when it runs the same command.
+    pub fn conflicts(&self, other: &Rule) -> bool {
+        !Ref::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Get the description of rule using its goals and outputs
+    pub fn describe(&self) -> String {
+        let outputs = self
+            .0
+            .api
+            .outputs()
+            .into_iter()
+            .map(|output| {
+                format!(
+                    "{} `{}`",
+                    if output.is_phony() { "goal" } else { "output" },
+                    output.name()
+                )
+            })
+            .collect::<Vec<_>>();
+        if outputs.is_empty() {
+            self.to_string()
+        } else {
+            format!("{} which produces {}", self, outputs.join(", "))
+        }
+    }
+
+    /// Get the inputs which changes causes rebuilding
+    ///
+    /// Includes explicit, implicit and discovered inputs.
+    pub fn tracked_inputs(&self) -> Vec<Artifact<Input>> {
+        let mut inputs = self.0.api.inputs();
+        inputs.extend(self.0.api.implicit_inputs());
+        inputs.extend(self.0.api.discovered_inputs().unwrap_or_default());
+        inputs
+    }
+
+    /// Get the inputs which should be built before
+    ///
+    /// Includes tracked and order-only inputs.
+    pub fn deps(&self) -> Vec<Artifact<Input>> {
+        let mut inputs = self.tracked_inputs();
+        inputs.extend(self.0.api.order_inputs());
+        inputs
+    }
+
     pub fn ready_inputs(&self) -> bool {
-        let inputs = self.0.api.inputs();
-        inputs.is_empty() || !inputs.into_iter().any(|input| input.outdated())
+        self.deps()
+            .into_iter()
+            .all(|input| matches!(input.state(), RuleState::Processed))
     }
 
     pub fn schedule(&self) {
         *self.0.state.write() = RuleState::Scheduled;
     }
 
-    pub async fn process(&self) -> Result<()> {
+    /// Get the signature of command
+    pub fn signature(&self) -> RuleSignature {
+        self.0.api.signature()
+    }
+
+    /// Get the files which is generated in addition to outputs
+    pub fn side_outputs(&self) -> Vec<String> {
+        self.0.api.side_outputs()
+    }
+
+    /// Get the timeout and retries of invoking
+    pub fn policy(&self) -> RulePolicy {
+        self.0.api.policy()
+    }
+
+    /// Get the digest of rule signature
+    pub fn signature_digest(&self) -> String {
+        let mut hasher = DataHasher::default();
+        for output in self.0.api.outputs() {
+            hasher.hash(output.name());
+        }
+        hasher.hash(&self.0.api.signature());
+        hasher.finish_base64_string()
+    }
+
+    /// Get the recorded duration of rule processing
+    pub fn duration(&self, db: &Database) -> Option<Duration> {
+        self.0
+            .api
+            .outputs()
+            .into_iter()
+            .filter(|output| !output.is_phony())
+            .find_map(|output| db.duration(output.name()))
+    }
+
+    /// Get the diagnostics of last invocation
+    pub fn diagnostics(&self) -> Diagnostics {
+        self.0.diagnostics.read().clone()
+    }
+
+    /// Get the names of inputs and outputs which cannot be discovered
+    fn declared_names(&self) -> Set<String> {
+        self.0
+            .api
+            .inputs()
+            .into_iter()
+            .chain(self.0.api.implicit_inputs())
+            .map(|input| input.name().clone())
+            .chain(
+                self.0
+                    .api
+                    .outputs()
+                    .into_iter()
+                    .map(|output| output.name().clone()),
+            )
+            .collect()
+    }
+
+    /// Get the sandbox which exposes inputs and directories of outputs only
+    fn sandbox(&self) -> Sandbox {
+        let mut sandbox = Sandbox::default();
+        for input in self.deps().into_iter().filter(|input| !input.is_phony()) {
+            sandbox.add_input(input.name());
+        }
+        for path in self.0.api.sandbox_paths() {
+            sandbox.add_input(path);
+        }
+        for output in self.output_files() {
+            sandbox.add_output(output);
+        }
+        sandbox
+    }
+
+    /// Get the files which is produced by rule including side outputs
+    fn output_files(&self) -> Vec<String> {
+        self.0
+            .api
+            .outputs()
+            .into_iter()
+            .filter(|output| !output.is_phony())
+            .map(|output| output.name().clone())
+            .chain(self.0.api.side_outputs())
+            .collect()
+    }
+
+    /// Check that rule discovers inputs but the discovered inputs was never recorded
+    ///
+    /// The rule should be invoked in that case because its real inputs is unknown.
+    pub fn is_discovery_unknown(&self, db: &Database) -> bool {
+        self.0.api.discovered_inputs().is_some()
+            && self
+                .0
+                .api
+                .outputs()
+                .into_iter()
+                .filter(|output| !output.is_phony())
+                .any(|output| db.output(output.name()).is_none())
+    }
+
+    /// Restore the inputs which was discovered under previous processing
+    ///
+    /// The recorded inputs will be merged with already known ones.
+    /// The recorded inputs which does not exists anymore will be skipped so rule will be invoked again.
+    pub async fn restore_discovered_inputs(&self, store: &ArtifactStore) -> Result<()> {
+        let known = if let Some(inputs) = self.0.api.discovered_inputs() {
+            inputs
+        } else {
+            return Ok(());
+        };
+        let names = if let Some(entry) = self
+            .0
+            .api
+            .outputs()
+            .into_iter()
+            .filter(|output| !output.is_phony())
+            .find_map(|output| store.database.output(output.name()))
+        {
+            entry.discovered
+        } else {
+            return Ok(());
+        };
+        let inputs = self
+            .discovered_artifacts(store, known.into_iter().collect(), names)
+            .await?;
+        self.0.api.set_discovered_inputs(inputs);
+        Ok(())
+    }
+
+    /// Merge the discovered inputs with known ones using its names
+    async fn discovered_artifacts(
+        &self,
+        store: &ArtifactStore,
+        mut inputs: Set<Artifact<Input>>,
+        names: impl IntoIterator<Item = String>,
+    ) -> Result<Set<Artifact<Input>>> {
+        let declared = self.declared_names();
+        for name in names.into_iter().filter(|name| !declared.contains(name)) {
+            if inputs.iter().any(|input| *input.name() == name) {
+                continue;
+            }
+            let input = Artifact::<Input, Actual>::new(store, name, "")?;
+            if input.rule().is_none() && input.init().await.is_err() {
+                log::debug!("Discovered input `{}` is missing", input.name());
+                continue;
+            }
+            inputs.insert(input.into_kind_any());
+        }
+        Ok(inputs)
+    }
+
+    /// Update the inputs which was discovered by rule while processing
+    ///
+    /// Includes the inputs reported by rule itself and the inputs from its dependency file.
+    async fn update_discovered_inputs(&self, store: &ArtifactStore) -> Result<()> {
+        let discovered = if let Some(discovered) = self.0.api.discovered_inputs() {
+            discovered
+        } else {
+            return Ok(());
+        };
+        let declared = self.declared_names();
+        let mut inputs = discovered
+            .into_iter()
+            .filter(|input| !declared.contains(input.name()))
+            .collect::<Set<_>>();
+        if let Some((depfile, kind)) = self.0.api.depfile() {
+            if Path::new(&depfile).is_file().await {
+                inputs.extend(
+                    store
+                        .read_deps(&depfile, kind, |name| !declared.contains(name))
+                        .await?
+                        .into_iter()
+                        .map(|input| input.into_kind_any()),
+                );
+            } else {
+                log::warn!(
+                    "Dependency file `{}` was not generated by {}",
+                    depfile,
+                    self
+                );
+            }
+        }
+        self.0.api.set_discovered_inputs(inputs);
+        Ok(())
+    }
+
+    /// Process rule
+    ///
+    /// Returns `false` when rule is up to date so invoking is not needed.
+    /// The commands will be executed in sandbox when `sandbox` is set.
+    /// The rule will be marked as cancelled when invoking is interrupted by `cancel`.
+    pub async fn process(
+        &self,
+        store: &ArtifactStore,
+        sandbox: bool,
+        cancel: &Cancel,
+    ) -> Result<bool> {
+        let db = &store.database;
         {
             *self.0.state.write() = RuleState::Processing;
         }
+        if self.outdated_reason(db).await?.is_none() {
+            log::debug!("{} is up to date", self);
+            {
+                *self.0.state.write() = RuleState::Processed;
+            }
+            return Ok(false);
+        }
         for output in self.0.api.outputs() {
             if let Some(dir) = Path::new(output.name()).parent() {
                 if !dir.is_dir().await {
@@ -133,7 +629,82 @@ impl Rule {
                 }
             }
         }
-        let diagnostics = self.0.api.clone().invoke().await?;
+        let guard = OutputsGuard::new(self, db).await;
+        if let Err(error) = self.produce(store, sandbox, cancel, &guard).await {
+            guard.cleanup().await;
+            return Err(error);
+        }
+        guard.disarm();
+        {
+            *self.0.state.write() = RuleState::Processed;
+        }
+        Ok(true)
+    }
+
+    /// Produce outputs by invoking rule or restoring from cache and record its state
+    async fn produce(
+        &self,
+        store: &ArtifactStore,
+        sandbox: bool,
+        cancel: &Cancel,
+        guard: &OutputsGuard,
+    ) -> Result<()> {
+        let db = &store.database;
+        let key = if store.cache.is_enabled() && self.0.api.cacheable() {
+            Some(self.cache_key(db).await?)
+        } else {
+            None
+        };
+        // previously discovered inputs is visible in sandbox too
+        let invocation = Invocation {
+            rule: self.to_string(),
+            sandbox: if sandbox { Some(self.sandbox()) } else { None },
+            // commands will be terminated on timeout without cancelling processing
+            cancel: cancel.child(),
+        };
+        if self.0.api.discovered_inputs().is_some() {
+            // inputs will be discovered again
+            self.0.api.set_discovered_inputs(Default::default());
+        }
+        let start = Instant::now();
+        let restored = if let Some(key) = &key {
+            let outputs = self.output_files();
+            store
+                .cache
+                .restore(key, &outputs)
+                .await
+                .unwrap_or_else(|error| {
+                    log::warn!("Unable to restore {} from cache due to: {}", self, error);
+                    None
+                })
+        } else {
+            None
+        };
+        let mut diagnostics = if let Some(discovered) = &restored {
+            log::debug!("{} restored from cache", self);
+            if self.0.api.discovered_inputs().is_some() {
+                // the inputs which was discovered when outputs was stored
+                let inputs = self
+                    .discovered_artifacts(store, Default::default(), discovered.keys().cloned())
+                    .await?;
+                self.0.api.set_discovered_inputs(inputs);
+            }
+            Diagnostics::default()
+        } else {
+            let result = self.invoke(invocation).await;
+            if result.is_err() && cancel.is_cancelled() {
+                log::warn!("{} cancelled", self);
+                {
+                    *self.0.state.write() = RuleState::Cancelled;
+                }
+                Err("Cancelled")?;
+            }
+            result?
+        };
+        let duration = start.elapsed();
+        if !diagnostics.is_failed() {
+            diagnostics.0.extend(guard.check().await);
+        }
         let is_failed = diagnostics.is_failed();
         {
             *self.0.diagnostics.write() = diagnostics;
@@ -142,18 +713,372 @@ impl Rule {
             Err(format!("Failed processing rule"))?;
         }
         let time = Time::now();
+        let mut digests = Map::default();
         for output in self.0.api.outputs() {
-            output.set_time(time);
+            let path = Path::new(output.name());
+            if output.is_phony() {
+                output.set_time(time);
+            } else if path.is_file().await {
+                let digest = DataHasher::hash_file_base64_string(path).await?;
+                if db
+                    .output(output.name())
+                    .map(|entry| entry.digest == digest)
+                    .unwrap_or(false)
+                {
+                    // early cutoff: the dependents compares recorded digests
+                    // so it will not be invoked when the output is the same
+                    log::debug!("Output `{}` is unchanged", output.name());
+                }
+                output.set_time(modified(path).await?);
+                digests.insert(output.name().clone(), digest);
+            } else {
+                output.set_time(modified(path).await.unwrap_or(time));
+            }
         }
-        {
-            *self.0.state.write() = RuleState::Processed;
+        self.update_discovered_inputs(store).await?;
+        let inputs = self.input_entries(db, None).await?;
+        if let (Some(key), None) = (&key, &restored) {
+            self.store_cached(&store.cache, key, &inputs).await;
         }
+        // keep the duration of invoking when outputs was restored from cache
+        self.record(
+            db,
+            inputs,
+            &digests,
+            if restored.is_some() {
+                None
+            } else {
+                Some(duration)
+            },
+        );
         Ok(())
     }
+
+    /// Invoke rule until it completes, times out or processing is cancelled
+    async fn invoke(&self, invocation: Invocation) -> Result<Diagnostics> {
+        let cancel = invocation.cancel.clone();
+        let invoke = cancel.run(self.0.api.clone().invoke(invocation));
+        let result = if let Some(timeout) = self.0.api.policy().timeout {
+            if let Ok(result) = async_std::future::timeout(timeout, invoke).await {
+                result
+            } else {
+                // terminate the commands which is still running
+                cancel.cancel();
+                Err(format!("Timed out after {:?}", timeout).into())
+            }
+        } else {
+            invoke.await
+        };
+        result.and_then(|result| result)
+    }
+
+    /// Get the key of build cache entry
+    ///
+    /// The discovered inputs is not taken into account because it will be checked on restoring.
+    async fn cache_key(&self, db: &Database) -> Result<String> {
+        let declared = self.declared_names();
+        let inputs = self.input_entries(db, None).await?;
+        Ok(Cache::key(
+            &self.signature_digest(),
+            inputs
+                .iter()
+                .filter(|(name, _)| declared.contains(*name))
+                .map(|(name, input)| (name, &input.digest)),
+        ))
+    }
+
+    /// Store outputs into build cache
+    async fn store_cached(&self, cache: &Cache, key: &str, inputs: &Map<String, InputEntry>) {
+        let files = self.output_files();
+        let discovered = self
+            .0
+            .api
+            .discovered_inputs()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|input| {
+                inputs
+                    .get(input.name())
+                    .map(|entry| (input.name().clone(), entry.digest.clone()))
+            })
+            .collect();
+        if let Err(error) = cache.store(key, &files, discovered).await {
+            log::warn!("Unable to store {} into cache due to: {}", self, error);
+        }
+    }
+
+    /// Get the reason why rule should be invoked
+    ///
+    /// Returns `None` when outputs is up to date. The recorded states of outputs is checked
+    /// using signature and digests of inputs, otherwise the timestamps will be compared.
+    /// When inputs contents does not changed since outputs was produced the recorded states will be refreshed.
+    pub async fn outdated_reason(&self, db: &Database) -> Result<Option<Reason>> {
+        let outputs = self.0.api.outputs();
+        if outputs.is_empty() || outputs.iter().any(|output| output.is_phony()) {
+            return Ok(Some(Reason::Phony));
+        }
+        let signature = self.signature_digest();
+        let mut entries = Vec::new();
+        for output in &outputs {
+            let time = output.time();
+            if time == Time::UNIX_EPOCH {
+                return Ok(Some(Reason::MissingOutput));
+            }
+            if let Some(entry) = db.output(output.name()) {
+                if entry.time != time {
+                    return Ok(Some(Reason::ChangedOutput {
+                        time,
+                        recorded: entry.time,
+                    }));
+                }
+                if entry.signature != signature {
+                    return Ok(Some(Reason::ChangedSignature));
+                }
+                entries.push(entry);
+            }
+        }
+        if entries.len() < outputs.len() {
+            // the outputs which was produced without recording is checked like make does
+            if self.is_discovery_unknown(db) {
+                return Ok(Some(Reason::UnknownInputs));
+            }
+            let inputs = self.tracked_inputs();
+            return Ok(outputs.iter().find_map(|output| {
+                inputs
+                    .iter()
+                    .find(|input| input.time() > output.time())
+                    .map(|input| Reason::NewerInput {
+                        input: input.name().clone(),
+                        time: input.time(),
+                        output: output.time(),
+                    })
+            }));
+        }
+        let entry = &entries[0];
+        let inputs = self.input_entries(db, Some(entry)).await?;
+        for (name, input) in &inputs {
+            match entry.inputs.get(name) {
+                Some(recorded) if recorded.digest != input.digest => {
+                    return Ok(Some(Reason::ChangedInput {
+                        input: name.clone(),
+                        time: input.time,
+                        recorded: recorded.time,
+                    }))
+                }
+                None => return Ok(Some(Reason::AddedInput(name.clone()))),
+                _ => (),
+            }
+        }
+        if let Some(name) = entry.inputs.keys().find(|name| !inputs.contains_key(*name)) {
+            return Ok(Some(Reason::RemovedInput(name.clone())));
+        }
+        if inputs
+            .iter()
+            .any(|(name, input)| entry.inputs[name].time != input.time)
+        {
+            let digests = outputs
+                .iter()
+                .zip(entries.iter())
+                .map(|(output, entry)| (output.name().clone(), entry.digest.clone()))
+                .collect();
+            self.record(db, inputs, &digests, None);
+        }
+        Ok(None)
+    }
+
+    /// Get the current states of inputs
+    ///
+    /// The digests of inputs which times is not changed will be reused from recorded entry
+    /// or from recorded state of rule which produces input.
+    async fn input_entries(
+        &self,
+        db: &Database,
+        recorded: Option<&OutputEntry>,
+    ) -> Result<Map<String, InputEntry>> {
+        let mut entries = Map::default();
+        for input in self.tracked_inputs() {
+            let time = input.time();
+            let digest = if input.is_phony() {
+                String::default()
+            } else if let Some(recorded) = recorded
+                .and_then(|entry| entry.inputs.get(input.name()))
+                .filter(|recorded| recorded.time == time)
+            {
+                recorded.digest.clone()
+            } else if let Some(produced) = db
+                .output(input.name())
+                .filter(|entry| entry.time == time && !entry.digest.is_empty())
+            {
+                produced.digest
+            } else {
+                DataHasher::hash_file_base64_string(Path::new(input.name())).await?
+            };
+            entries.insert(input.name().clone(), InputEntry { time, digest });
+        }
+        Ok(entries)
+    }
+
+    /// Record the states of outputs
+    ///
+    /// The previously recorded duration will be kept when no duration passed.
+    fn record(
+        &self,
+        db: &Database,
+        inputs: Map<String, InputEntry>,
+        digests: &Map<String, String>,
+        duration: Option<Duration>,
+    ) {
+        let signature = self.signature_digest();
+        let side = self.0.api.side_outputs();
+        let discovered = self
+            .0
+            .api
+            .discovered_inputs()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|input| input.name().clone())
+            .collect::<Vec<_>>();
+        for output in self.0.api.outputs() {
+            if !output.is_phony() {
+                db.set_output(
+                    output.name(),
+                    OutputEntry {
+                        signature: signature.clone(),
+                        time: output.time(),
+                        digest: digests.get(output.name()).cloned().unwrap_or_default(),
+                        duration: duration
+                            .or_else(|| db.duration(output.name()))
+                            .unwrap_or_default(),
+                        inputs: inputs.clone(),
+                        side: side.clone(),
+                        discovered: discovered.clone(),
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// The guard which removes incomplete outputs when rule processing fails or cancelled
+///
+/// The outputs which was not modified under processing will be kept.
+struct OutputsGuard {
+    db: Database,
+    /// The output files with its times before processing
+    files: Vec<(String, Option<Time>)>,
+    /// The declared outputs which files is placed first
+    outputs: Vec<Artifact<Output>>,
+    armed: bool,
+}
+
+impl OutputsGuard {
+    async fn new(rule: &Rule, db: &Database) -> Self {
+        let outputs = rule
+            .0
+            .api
+            .outputs()
+            .into_iter()
+            .filter(|output| !output.is_phony())
+            .collect::<Vec<_>>();
+        let mut files = Vec::new();
+        for name in outputs
+            .iter()
+            .map(|output| output.name().clone())
+            .chain(rule.side_outputs())
+        {
+            let time = Self::modified(&name).await;
+            files.push((name, time));
+        }
+        Self {
+            db: db.clone(),
+            files,
+            outputs,
+            armed: true,
+        }
+    }
+
+    async fn modified(name: &str) -> Option<Time> {
+        modified(Path::new(name)).await.ok()
+    }
+
+    /// Check that outputs was produced
+    async fn check(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for (name, time) in &self.files[..self.outputs.len()] {
+            let (severity, message) = if !Path::new(name).is_file().await {
+                (Severity::Error, "Output was not produced by rule")
+            } else if time.is_some() && Self::modified(name).await == *time {
+                (Severity::Note, "Output was not modified by rule")
+            } else {
+                continue;
+            };
+            diagnostics.push(Diagnostic {
+                severity,
+                message: message.into(),
+                locations: vec![Location {
+                    file: name.clone(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            });
+        }
+        diagnostics
+    }
+
+    /// Remove outputs which was modified under processing
+    async fn cleanup(mut self) {
+        for (index, (name, time)) in self.files.iter().enumerate() {
+            let current = Self::modified(name).await;
+            if current.is_some() && current != *time {
+                log::warn!("Remove incomplete output `{}`", name);
+                if let Err(error) = remove_file(name).await {
+                    log::error!("Unable to remove `{}` due to: {}", name, error);
+                }
+                self.remove(index);
+            }
+        }
+        self.armed = false;
+    }
+
+    /// Forget the state of removed output so it will be treated as missing
+    fn remove(&self, index: usize) {
+        let name = &self.files[index].0;
+        if let Some(output) = self.outputs.get(index) {
+            output.set_time(Time::UNIX_EPOCH);
+        }
+        self.db.remove_output(name);
+    }
+
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for OutputsGuard {
+    /// The last resort when processing future is dropped before completion
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        for (index, (name, time)) in self.files.iter().enumerate() {
+            let current = std::fs::metadata(name)
+                .and_then(|meta| meta.modified())
+                .ok();
+            if current.is_some() && current != *time {
+                log::warn!("Remove incomplete output `{}`", name);
+                if let Err(error) = std::fs::remove_file(name) {
+                    log::error!("Unable to remove `{}` due to: {}", name, error);
+                }
+                self.remove(index);
+            }
+        }
+    }
 }
 
 pub struct NoInternal {
     inputs: Mut<Set<Artifact<Input>>>,
+    implicit_inputs: Mut<Set<Artifact<Input>>>,
+    order_inputs: Mut<Set<Artifact<Input>>>,
     outputs: WeakSet<WeakArtifact<Output>>,
 }
 
@@ -178,17 +1103,40 @@ impl NoRule {
         Rule::from_api(self.0.clone())
     }
 
-    pub fn new_raw(inputs: Set<Artifact<Input>>, outputs: WeakSet<WeakArtifact<Output>>) -> Self {
+    pub fn new_raw(
+        inputs: Set<Artifact<Input>>,
+        outputs: WeakSet<WeakArtifact<Output>>,
+    ) -> Result<Self> {
+        Self::new_opts(inputs, outputs, Default::default())
+    }
+
+    /// Create rule using options
+    ///
+    /// Only the options which is applicable to rule without function is used.
+    pub fn new_opts(
+        inputs: Set<Artifact<Input>>,
+        outputs: WeakSet<WeakArtifact<Output>>,
+        opts: RuleOptions,
+    ) -> Result<Self> {
         let inputs = Mut::new(inputs);
-        let this = Self(Ref::new(NoInternal { inputs, outputs }));
+        let this = Self(Ref::new(NoInternal {
+            inputs,
+            implicit_inputs: Mut::new(opts.implicit_inputs),
+            order_inputs: Mut::new(opts.order_inputs),
+            outputs,
+        }));
         log::debug!("NoRule::new");
         {
             let rule = this.to_dyn();
             for output in &this.0.outputs {
-                output.set_rule(rule.clone());
+                if opts.overrides {
+                    output.override_rule(rule.clone());
+                } else {
+                    output.set_rule(rule.clone())?;
+                }
             }
         }
-        this
+        Ok(this)
     }
 }
 
@@ -197,11 +1145,24 @@ impl RuleApi for NoInternal {
         self.inputs.read().iter().cloned().collect()
     }
 
+    fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+        self.implicit_inputs.read().iter().cloned().collect()
+    }
+
+    fn order_inputs(&self) -> Vec<Artifact<Input>> {
+        self.order_inputs.read().iter().cloned().collect()
+    }
+
     fn outputs(&self) -> Vec<Artifact<Output>> {
         self.outputs.iter().collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
+    fn signature(&self) -> RuleSignature {
+        // nothing is invoked so nothing can be changed
+        RuleSignature::default()
+    }
+
+    fn invoke(self: Ref<Self>, _invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
         async { Ok(Diagnostics::default()) }.boxed_local()
     }
 }
@@ -209,7 +1170,16 @@ impl RuleApi for NoInternal {
 #[derive(qjs::HasRefs)]
 pub struct JsInternal {
     inputs: Mut<Set<Artifact<Input>>>,
+    implicit_inputs: Mut<Set<Artifact<Input>>>,
+    order_inputs: Mut<Set<Artifact<Input>>>,
+    discovered_inputs: Mut<Set<Artifact<Input>>>,
     outputs: WeakSet<WeakArtifact<Output>>,
+    pool: Mut<Option<String>>,
+    depfile: Mut<Option<(String, DepKind)>>,
+    policy: Mut<RulePolicy>,
+    signature: RuleSignature,
+    toolchain: Vec<String>,
+    invocation: Mut<Invocation>,
     #[quickjs(has_refs)]
     function: qjs::Persistent<qjs::Function<'static>>,
     context: qjs::Context,
@@ -241,16 +1211,43 @@ impl JsRule {
         Rule::from_api(self.0.clone())
     }
 
-    pub fn new_raw(
+    /// Get the environment of current invoking
+    pub fn invocation(&self) -> Invocation {
+        self.0.invocation.read().clone()
+    }
+
+    pub fn new_raw<'js>(
         inputs: Set<Artifact<Input>>,
         outputs: WeakSet<WeakArtifact<Output>>,
         function: qjs::Persistent<qjs::Function<'static>>,
-        context: qjs::Context,
-    ) -> Self {
+        ctx: qjs::Ctx<'js>,
+        opts: RuleOptions,
+    ) -> Result<Self> {
+        let context = qjs::Context::from_ctx(ctx)?;
+        // the source of function with user-supplied key is used as signature
+        // because the commands which will be executed is unknown before invoking
+        let source: String = ctx
+            .globals()
+            .get::<_, qjs::Function>("String")?
+            .call((function.clone().restore(ctx)?,))?;
+        let signature = RuleSignature {
+            cmd: source,
+            args: opts.signature,
+            ..Default::default()
+        };
         let inputs = Mut::new(inputs);
         let this = Self(Ref::new(JsInternal {
             inputs,
+            implicit_inputs: Mut::new(opts.implicit_inputs),
+            order_inputs: Mut::new(opts.order_inputs),
+            discovered_inputs: Default::default(),
             outputs,
+            pool: Mut::new(opts.pool),
+            depfile: Mut::new(opts.depfile),
+            policy: Mut::new(opts.policy),
+            signature,
+            toolchain: opts.toolchain,
+            invocation: Default::default(),
             function,
             context,
         }));
@@ -258,10 +1255,14 @@ impl JsRule {
         {
             let rule = this.to_dyn();
             for output in &this.0.outputs {
-                output.set_rule(rule.clone());
+                if opts.overrides {
+                    output.override_rule(rule.clone());
+                } else {
+                    output.set_rule(rule.clone())?;
+                }
             }
         }
-        this
+        Ok(this)
     }
 }
 
@@ -270,23 +1271,110 @@ impl RuleApi for JsInternal {
         self.inputs.read().iter().cloned().collect()
     }
 
+    fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+        self.implicit_inputs.read().iter().cloned().collect()
+    }
+
+    fn order_inputs(&self) -> Vec<Artifact<Input>> {
+        self.order_inputs.read().iter().cloned().collect()
+    }
+
     fn outputs(&self) -> Vec<Artifact<Output>> {
         self.outputs.iter().collect()
     }
 
-    fn invoke(self: Ref<Self>) -> BoxedFuture<Result<Diagnostics>> {
+    fn signature(&self) -> RuleSignature {
+        self.signature.clone()
+    }
+
+    fn pool(&self) -> Option<String> {
+        self.pool.read().clone()
+    }
+
+    fn side_outputs(&self) -> Vec<String> {
+        self.depfile
+            .read()
+            .iter()
+            .map(|(depfile, _)| depfile.clone())
+            .collect()
+    }
+
+    fn depfile(&self) -> Option<(String, DepKind)> {
+        self.depfile.read().clone()
+    }
+
+    fn discovered_inputs(&self) -> Option<Vec<Artifact<Input>>> {
+        Some(self.discovered_inputs.read().iter().cloned().collect())
+    }
+
+    fn set_discovered_inputs(&self, inputs: Set<Artifact<Input>>) {
+        *self.discovered_inputs.write() = inputs;
+    }
+
+    fn policy(&self) -> RulePolicy {
+        *self.policy.read()
+    }
+
+    fn sandbox_paths(&self) -> Vec<String> {
+        self.toolchain.clone()
+    }
+
+    fn invoke(self: Ref<Self>, invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+        *self.invocation.write() = invocation;
         let function = self.function.clone();
         let context = self.context.clone();
         let this = JsRule(self);
         async move {
-            let promise: qjs::Promise<_> =
-                context.with(|ctx| function.restore(ctx)?.call((qjs::This(this),)))?;
-            Ok(promise.await?)
+            let promise: qjs::Promise<JsResult> =
+                context.with(|ctx| function.restore(ctx)?.call((qjs::This(this.clone()),)))?;
+            let result = promise.await?;
+            this.0
+                .set_discovered_inputs(result.inputs.unwrap_or_default());
+            Ok(result.diagnostics)
         }
         .boxed_local()
     }
 }
 
+/// The rule object which is passed from JS by reference
+impl<'js> qjs::FromJs<'js> for JsRule {
+    fn from_js(ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        let rule: &JsRule = qjs::FromJs::from_js(ctx, val)?;
+        Ok(rule.clone())
+    }
+}
+
+/// The result of rule function
+///
+/// Function may return either diagnostics or an object with diagnostics and discovered inputs.
+#[derive(Default)]
+struct JsResult {
+    diagnostics: Diagnostics,
+    inputs: Option<Set<Artifact<Input>>>,
+}
+
+impl<'js> qjs::FromJs<'js> for JsResult {
+    fn from_js(_ctx: qjs::Ctx<'js>, val: qjs::Value<'js>) -> qjs::Result<Self> {
+        Ok(match val.type_of() {
+            qjs::Type::Undefined | qjs::Type::Null => Self::default(),
+            qjs::Type::Array => Self {
+                diagnostics: val.get()?,
+                inputs: None,
+            },
+            qjs::Type::Object => {
+                let obj: qjs::Object = val.get()?;
+                Self {
+                    diagnostics: obj
+                        .get::<_, Option<Diagnostics>>("diagnostics")?
+                        .unwrap_or_default(),
+                    inputs: obj.get("inputs")?,
+                }
+            }
+            ty => return Err(qjs::Error::new_from_js(ty.as_str(), "rule result")),
+        })
+    }
+}
+
 #[qjs::bind(module, public)]
 #[quickjs(bare)]
 mod js {
@@ -308,6 +1396,11 @@ mod js {
             self.0.api.outputs()
         }
 
+        #[quickjs(get, enumerable)]
+        pub fn pool(&self) -> Option<String> {
+            self.0.api.pool()
+        }
+
         #[quickjs(rename = "toString")]
         pub fn to_string_js(&self) -> String {
             self.to_string()
@@ -319,12 +1412,14 @@ mod js {
         inputs: Either<Set<Artifact<Input>>, Artifact<Input>>,
         outputs: Either<Set<Artifact<Output>>, Artifact<Output>>,
         function: qjs::Persistent<qjs::Function<'static>>,
+        opts: qjs::Opt<RuleOptions>,
         ctx: qjs::Ctx<'js>,
-    ) -> JsRule {
+    ) -> Result<JsRule> {
         JsRule::new_(
             function,
             qjs::Opt(Some(outputs)),
             qjs::Opt(Some(inputs)),
+            opts,
             ctx,
         )
     }
@@ -334,12 +1429,14 @@ mod js {
         function: qjs::Persistent<qjs::Function<'static>>,
         outputs: Either<Set<Artifact<Output>>, Artifact<Output>>,
         inputs: Either<Set<Artifact<Input>>, Artifact<Input>>,
+        opts: qjs::Opt<RuleOptions>,
         ctx: qjs::Ctx<'js>,
-    ) -> JsRule {
+    ) -> Result<JsRule> {
         JsRule::new_(
             function,
             qjs::Opt(Some(outputs)),
             qjs::Opt(Some(inputs)),
+            opts,
             ctx,
         )
     }
@@ -349,16 +1446,17 @@ mod js {
         function: qjs::Persistent<qjs::Function<'static>>,
         outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
         inputs: qjs::Opt<Either<Set<Artifact<Input>>, Artifact<Input>>>,
+        opts: qjs::Opt<RuleOptions>,
         ctx: qjs::Ctx<'js>,
-    ) -> JsRule {
-        JsRule::new_(function, outputs, inputs, ctx)
+    ) -> Result<JsRule> {
+        JsRule::new_(function, outputs, inputs, opts, ctx)
     }
 
     #[quickjs(rename = "Rule")]
     pub fn rule_no1<'js>(
         inputs: Either<Set<Artifact<Input>>, Artifact<Input>>,
         outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
-    ) -> NoRule {
+    ) -> Result<NoRule> {
         NoRule::new_(outputs, qjs::Opt(Some(inputs)))
     }
 
@@ -366,7 +1464,7 @@ mod js {
     pub fn rule_no2<'js>(
         outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
         inputs: qjs::Opt<Either<Set<Artifact<Input>>, Artifact<Input>>>,
-    ) -> NoRule {
+    ) -> Result<NoRule> {
         NoRule::new_(outputs, inputs)
     }
 
@@ -376,7 +1474,7 @@ mod js {
         pub fn new(
             inputs: Either<Set<Artifact<Input>>, Artifact<Input>>,
             outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
-        ) -> Self {
+        ) -> Result<Self> {
             Self::new_(outputs, qjs::Opt(Some(inputs)))
         }
 
@@ -384,7 +1482,7 @@ mod js {
         pub fn new_(
             outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
             inputs: qjs::Opt<Either<Set<Artifact<Input>>, Artifact<Input>>>,
-        ) -> Self {
+        ) -> Result<Self> {
             let inputs = inputs
                 .0
                 .map(|inputs| inputs.either(|inputs| inputs, |input| once(input).collect()))
@@ -411,6 +1509,28 @@ mod js {
             *self.0.inputs.write() = inputs.either(|inputs| inputs, |input| once(input).collect());
         }
 
+        #[quickjs(rename = "implicitInputs", get, enumerable)]
+        pub fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+            self.0.implicit_inputs.read().iter().cloned().collect()
+        }
+
+        #[quickjs(rename = "implicitInputs", set)]
+        pub fn set_implicit_inputs(&self, inputs: Either<Set<Artifact<Input>>, Artifact<Input>>) {
+            *self.0.implicit_inputs.write() =
+                inputs.either(|inputs| inputs, |input| once(input).collect());
+        }
+
+        #[quickjs(rename = "orderInputs", get, enumerable)]
+        pub fn order_inputs(&self) -> Vec<Artifact<Input>> {
+            self.0.order_inputs.read().iter().cloned().collect()
+        }
+
+        #[quickjs(rename = "orderInputs", set)]
+        pub fn set_order_inputs(&self, inputs: Either<Set<Artifact<Input>>, Artifact<Input>>) {
+            *self.0.order_inputs.write() =
+                inputs.either(|inputs| inputs, |input| once(input).collect());
+        }
+
         #[quickjs(get, enumerable)]
         pub fn outputs(&self) -> Vec<Artifact<Output>> {
             self.0.outputs.iter().collect()
@@ -428,12 +1548,14 @@ mod js {
             inputs: Either<Set<Artifact<Input>>, Artifact<Input>>,
             outputs: Either<Set<Artifact<Output>>, Artifact<Output>>,
             function: qjs::Persistent<qjs::Function<'static>>,
+            opts: qjs::Opt<RuleOptions>,
             ctx: qjs::Ctx<'js>,
-        ) -> Self {
+        ) -> Result<Self> {
             Self::new_(
                 function,
                 qjs::Opt(Some(outputs)),
                 qjs::Opt(Some(inputs)),
+                opts,
                 ctx,
             )
         }
@@ -443,9 +1565,10 @@ mod js {
             function: qjs::Persistent<qjs::Function<'static>>,
             outputs: qjs::Opt<Either<Set<Artifact<Output>>, Artifact<Output>>>,
             inputs: qjs::Opt<Either<Set<Artifact<Input>>, Artifact<Input>>>,
+            opts: qjs::Opt<RuleOptions>,
             ctx: qjs::Ctx<'js>,
-        ) -> Self {
-            let context = qjs::Context::from_ctx(ctx).unwrap();
+        ) -> Result<Self> {
+            let opts = opts.0.unwrap_or_default();
             let inputs = inputs
                 .0
                 .map(|inputs| inputs.either(|inputs| inputs, |input| once(input).collect()))
@@ -459,7 +1582,7 @@ mod js {
                     )
                 })
                 .unwrap_or_default();
-            Self::new_raw(inputs, outputs, function, context)
+            Self::new_raw(inputs, outputs, function, ctx, opts)
         }
 
         #[quickjs(get, enumerable)]
@@ -472,14 +1595,696 @@ mod js {
             *self.0.inputs.write() = inputs.either(|inputs| inputs, |input| once(input).collect());
         }
 
+        #[quickjs(rename = "implicitInputs", get, enumerable)]
+        pub fn implicit_inputs(&self) -> Vec<Artifact<Input>> {
+            self.0.implicit_inputs.read().iter().cloned().collect()
+        }
+
+        #[quickjs(rename = "implicitInputs", set)]
+        pub fn set_implicit_inputs(&self, inputs: Either<Set<Artifact<Input>>, Artifact<Input>>) {
+            *self.0.implicit_inputs.write() =
+                inputs.either(|inputs| inputs, |input| once(input).collect());
+        }
+
+        #[quickjs(rename = "orderInputs", get, enumerable)]
+        pub fn order_inputs(&self) -> Vec<Artifact<Input>> {
+            self.0.order_inputs.read().iter().cloned().collect()
+        }
+
+        #[quickjs(rename = "orderInputs", set)]
+        pub fn set_order_inputs(&self, inputs: Either<Set<Artifact<Input>>, Artifact<Input>>) {
+            *self.0.order_inputs.write() =
+                inputs.either(|inputs| inputs, |input| once(input).collect());
+        }
+
         #[quickjs(get, enumerable)]
         pub fn outputs(&self) -> Vec<Artifact<Output>> {
             self.0.outputs.iter().collect()
         }
 
+        #[quickjs(get, enumerable)]
+        pub fn pool(&self) -> Option<String> {
+            self.0.pool.read().clone()
+        }
+
+        #[quickjs(rename = "pool", set)]
+        pub fn set_pool(&self, pool: Option<String>) {
+            *self.0.pool.write() = pool;
+        }
+
+        #[quickjs(get, enumerable)]
+        pub fn depfile(&self) -> Option<String> {
+            self.0
+                .depfile
+                .read()
+                .as_ref()
+                .map(|(depfile, _)| depfile.clone())
+        }
+
+        #[quickjs(rename = "depfileFormat", get, enumerable)]
+        pub fn depfile_format(&self) -> Option<DepKind> {
+            self.0.depfile.read().as_ref().map(|(_, kind)| *kind)
+        }
+
+        #[quickjs(rename = "discoveredInputs", get, enumerable)]
+        pub fn discovered_inputs(&self) -> Vec<Artifact<Input>> {
+            self.0.discovered_inputs.read().iter().cloned().collect()
+        }
+
+        #[quickjs(get, enumerable)]
+        pub fn timeout(&self) -> Option<u64> {
+            self.0
+                .policy
+                .read()
+                .timeout
+                .map(|timeout| timeout.as_millis() as u64)
+        }
+
+        #[quickjs(rename = "timeout", set)]
+        pub fn set_timeout(&self, timeout: Option<u64>) {
+            self.0.policy.write().timeout = timeout.map(Duration::from_millis);
+        }
+
+        #[quickjs(get, enumerable)]
+        pub fn retries(&self) -> usize {
+            self.0.policy.read().retries
+        }
+
+        #[quickjs(rename = "retries", set)]
+        pub fn set_retries(&self, retries: usize) {
+            self.0.policy.write().retries = retries;
+        }
+
+        #[quickjs(rename = "retryDelay", get, enumerable)]
+        pub fn retry_delay(&self) -> u64 {
+            self.0.policy.read().retry_delay.as_millis() as u64
+        }
+
+        #[quickjs(rename = "retryDelay", set)]
+        pub fn set_retry_delay(&self, delay: u64) {
+            self.0.policy.write().retry_delay = Duration::from_millis(delay);
+        }
+
+        #[quickjs(get, enumerable)]
+        pub fn sandbox(&self) -> Option<Sandbox> {
+            self.0.invocation.read().sandbox.clone()
+        }
+
         #[quickjs(rename = "toString")]
         pub fn to_string_js(&self) -> String {
             self.to_string()
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::system::write_file;
+
+    /// The native rule which writes its flags to output
+    ///
+    /// Fails without writing with `--error` flag and after writing with `--fail` flag.
+    /// Succeeds without writing with `--skip` flag.
+    struct FlagsRule {
+        input: Artifact<Input>,
+        output: WeakArtifact<Output>,
+        flags: Vec<String>,
+    }
+
+    impl RuleApi for FlagsRule {
+        fn inputs(&self) -> Vec<Artifact<Input>> {
+            vec![self.input.clone()]
+        }
+
+        fn outputs(&self) -> Vec<Artifact<Output>> {
+            self.output.try_ref().into_iter().collect()
+        }
+
+        fn signature(&self) -> RuleSignature {
+            RuleSignature::exec("cc", self.flags.iter().cloned())
+        }
+
+        fn invoke(self: Ref<Self>, _invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+            async move {
+                let has_flag = |flag: &str| self.flags.iter().any(|arg| arg == flag);
+                if has_flag("--error") {
+                    return Err("Unable to start".into());
+                }
+                if let Some(output) = self.output.try_ref() {
+                    if !has_flag("--skip") {
+                        write_file(output.name(), self.flags.join(" ")).await?;
+                    }
+                }
+                if has_flag("--fail") {
+                    return Err("Failed after writing".into());
+                }
+                Ok(Diagnostics::default())
+            }
+            .boxed_local()
+        }
+    }
+
+    fn declare(
+        input: &Artifact<Input>,
+        output: &Artifact<Output, Actual>,
+        flags: &[&str],
+    ) -> Result<Rule> {
+        let rule = Rule::from_api(Ref::new(FlagsRule {
+            input: input.clone(),
+            output: output.clone().into_kind_any().weak(),
+            flags: flags.iter().map(|flag| flag.to_string()).collect(),
+        }));
+        output.set_rule(rule.clone())?;
+        Ok(rule)
+    }
+
+    fn path(dir: &std::path::Path, name: &str) -> String {
+        dir.join(name).display().to_string()
+    }
+
+    /// Run build like a new gear process does and get the reasons of scheduled rules
+    async fn build(dir: &std::path::Path, flags: &[&str]) -> Result<Vec<Reason>> {
+        let store = ArtifactStore::default();
+        store.database.open(dir).await?;
+        let src = path(dir, "main.c");
+        if !Path::new(&src).is_file().await {
+            write_file(&src, "int main() {}").await?;
+        }
+        let input = Artifact::<Input, Actual>::new_init(&store, src, "").await?;
+        let output = Artifact::<Output, Actual>::new_init(&store, path(dir, "main.o"), "").await?;
+        let rule = declare(&input.into_kind_any(), &output, flags)?;
+        let mut reasons = Vec::new();
+        output
+            .process(&store.database, &mut Map::default(), &mut |_, reason| {
+                reasons.push(reason)
+            })
+            .await?;
+        if !reasons.is_empty() {
+            rule.process(&store, false, &Cancel::default()).await?;
+        }
+        store.database.save().await?;
+        Ok(reasons)
+    }
+
+    #[async_std::test]
+    async fn native_rule_signature() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert_eq!(
+            build(dir.path(), &["-O0"]).await.unwrap(),
+            [Reason::MissingOutput]
+        );
+        assert!(build(dir.path(), &["-O0"]).await.unwrap().is_empty());
+        assert_eq!(
+            build(dir.path(), &["-O2"]).await.unwrap(),
+            [Reason::ChangedSignature]
+        );
+        assert!(build(dir.path(), &["-O2"]).await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn outdated_reasons() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = path(dir.path(), "main.c");
+        let obj = path(dir.path(), "main.o");
+        let later = |secs| Time::now() + Duration::from_secs(secs);
+
+        assert_eq!(
+            build(dir.path(), &["-O0"]).await.unwrap(),
+            [Reason::MissingOutput]
+        );
+
+        // the input is touched but its contents is the same
+        crate::system::set_modified(&src, later(1)).await.unwrap();
+        assert!(build(dir.path(), &["-O0"]).await.unwrap().is_empty());
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert_eq!(
+            db.output(&obj).unwrap().inputs[&src].time,
+            modified(Path::new(&src)).await.unwrap()
+        );
+
+        write_file(&src, "int main() { return 0; }").await.unwrap();
+        crate::system::set_modified(&src, later(2)).await.unwrap();
+        assert!(matches!(
+            &build(dir.path(), &["-O0"]).await.unwrap()[..],
+            [Reason::ChangedInput { input, .. }] if *input == src
+        ));
+
+        crate::system::set_modified(&obj, later(3)).await.unwrap();
+        assert!(matches!(
+            &build(dir.path(), &["-O0"]).await.unwrap()[..],
+            [Reason::ChangedOutput { .. }]
+        ));
+
+        remove_file(&obj).await.unwrap();
+        assert_eq!(
+            build(dir.path(), &["-O0"]).await.unwrap(),
+            [Reason::MissingOutput]
+        );
+
+        // the outputs without recorded states is checked by timestamps
+        remove_file(dir.path().join(crate::DATABASE_FILE))
+            .await
+            .unwrap();
+        crate::system::set_modified(&obj, later(5)).await.unwrap();
+        assert!(build(dir.path(), &["-O0"]).await.unwrap().is_empty());
+        crate::system::set_modified(&src, later(10)).await.unwrap();
+        assert!(matches!(
+            &build(dir.path(), &["-O0"]).await.unwrap()[..],
+            [Reason::NewerInput { input, .. }] if *input == src
+        ));
+        assert!(build(dir.path(), &["-O0"]).await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn incomplete_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let obj = path(dir.path(), "main.o");
+
+        for (flag, kept) in &[("--error", true), ("--fail", false)] {
+            build(dir.path(), &["-O0"]).await.unwrap();
+
+            let store = ArtifactStore::default();
+            store.database.open(dir.path()).await.unwrap();
+            let input = Artifact::<Input, Actual>::new_init(&store, path(dir.path(), "main.c"), "")
+                .await
+                .unwrap();
+            let output = Artifact::<Output, Actual>::new_init(&store, obj.clone(), "")
+                .await
+                .unwrap();
+            let rule = declare(&input.into_kind_any(), &output, &[*flag]).unwrap();
+            assert!(rule
+                .process(&store, false, &Cancel::default())
+                .await
+                .is_err());
+            // the output which was rewritten by failed rule is removed
+            assert_eq!(Path::new(&obj).is_file().await, *kept);
+            assert_eq!(store.database.output(&obj).is_some(), *kept);
+        }
+    }
+
+    #[async_std::test]
+    async fn output_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let obj = path(dir.path(), "main.o");
+        write_file(path(dir.path(), "main.c"), "int main() {}")
+            .await
+            .unwrap();
+
+        let process = |flags: &'static [&'static str]| {
+            let dir = dir.path().to_owned();
+            let obj = obj.clone();
+            async move {
+                let store = ArtifactStore::default();
+                store.database.open(dir.as_path()).await.unwrap();
+                let input = Artifact::<Input, Actual>::new_init(&store, path(&dir, "main.c"), "")
+                    .await
+                    .unwrap();
+                let output = Artifact::<Output, Actual>::new_init(&store, obj, "")
+                    .await
+                    .unwrap();
+                let rule = declare(&input.into_kind_any(), &output, flags).unwrap();
+                let result = rule.process(&store, false, &Cancel::default()).await;
+                store.database.save().await.unwrap();
+                (result.is_ok(), rule.diagnostics().0)
+            }
+        };
+
+        let (success, diagnostics) = process(&["--skip"]).await;
+        assert!(!success);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].severity, Severity::Error));
+        assert_eq!(diagnostics[0].message, "Output was not produced by rule");
+        assert_eq!(diagnostics[0].locations[0].file, obj);
+
+        assert!(process(&["-O0"]).await.0);
+
+        // the output which is not rewritten is reported but accepted
+        let (success, diagnostics) = process(&["-O0", "--skip"]).await;
+        assert!(success);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(matches!(diagnostics[0].severity, Severity::Note));
+        assert_eq!(diagnostics[0].message, "Output was not modified by rule");
+    }
+
+    /// The native rule which writes dependency file like compiler does
+    ///
+    /// The headers will be reported by rule itself without dependency file when `reported` is set.
+    struct DepRule {
+        input: Artifact<Input>,
+        output: WeakArtifact<Output>,
+        headers: Vec<String>,
+        reported: Option<Vec<Artifact<Input>>>,
+        discovered: Mut<Set<Artifact<Input>>>,
+    }
+
+    impl DepRule {
+        fn depfile_name(&self) -> String {
+            self.output
+                .try_ref()
+                .map(|output| format!("{}.d", output.name()))
+                .unwrap_or_default()
+        }
+    }
+
+    impl RuleApi for DepRule {
+        fn inputs(&self) -> Vec<Artifact<Input>> {
+            vec![self.input.clone()]
+        }
+
+        fn outputs(&self) -> Vec<Artifact<Output>> {
+            self.output.try_ref().into_iter().collect()
+        }
+
+        fn signature(&self) -> RuleSignature {
+            RuleSignature::exec("cc", once("-MD"))
+        }
+
+        fn side_outputs(&self) -> Vec<String> {
+            self.depfile().into_iter().map(|(name, _)| name).collect()
+        }
+
+        fn depfile(&self) -> Option<(String, DepKind)> {
+            if self.reported.is_some() {
+                None
+            } else {
+                Some((self.depfile_name(), DepKind::Make))
+            }
+        }
+
+        fn cacheable(&self) -> bool {
+            true
+        }
+
+        fn discovered_inputs(&self) -> Option<Vec<Artifact<Input>>> {
+            Some(self.discovered.read().iter().cloned().collect())
+        }
+
+        fn set_discovered_inputs(&self, inputs: Set<Artifact<Input>>) {
+            *self.discovered.write() = inputs;
+        }
+
+        fn invoke(self: Ref<Self>, _invocation: Invocation) -> BoxedFuture<Result<Diagnostics>> {
+            async move {
+                if let Some(output) = self.output.try_ref() {
+                    write_file(output.name(), "").await?;
+                    if let Some(reported) = &self.reported {
+                        self.set_discovered_inputs(reported.iter().cloned().collect());
+                        return Ok(Diagnostics::default());
+                    }
+                    let deps = format!(
+                        "{}: {} {}\n",
+                        output.name(),
+                        self.input.name(),
+                        self.headers.join(" ")
+                    );
+                    write_file(self.depfile_name(), deps).await?;
+                }
+                Ok(Diagnostics::default())
+            }
+            .boxed_local()
+        }
+    }
+
+    /// Run build of rule with dependency file which already knows some headers
+    async fn dep_build(dir: &std::path::Path, known: &[&str]) -> Result<Vec<Reason>> {
+        let store = ArtifactStore::default();
+        store.database.open(dir).await?;
+        let input = Artifact::<Input, Actual>::new_init(&store, path(dir, "main.c"), "").await?;
+        let output = Artifact::<Output, Actual>::new_init(&store, path(dir, "main.o"), "").await?;
+        let mut discovered = Set::default();
+        for name in known {
+            let header = Artifact::<Input, Actual>::new_init(&store, path(dir, name), "").await?;
+            discovered.insert(header.into_kind_any());
+        }
+        let rule = Rule::from_api(Ref::new(DepRule {
+            input: input.into_kind_any(),
+            output: output.clone().into_kind_any().weak(),
+            headers: vec![path(dir, "main.h"), path(dir, "util.h")],
+            reported: None,
+            discovered: Mut::new(discovered),
+        }));
+        output.set_rule(rule.clone())?;
+        rule.restore_discovered_inputs(&store).await?;
+        let mut reasons = Vec::new();
+        output
+            .process(&store.database, &mut Map::default(), &mut |_, reason| {
+                reasons.push(reason)
+            })
+            .await?;
+        if !reasons.is_empty() {
+            rule.process(&store, false, &Cancel::default()).await?;
+        }
+        store.database.save().await?;
+        Ok(reasons)
+    }
+
+    #[async_std::test]
+    async fn discovered_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &["main.c", "main.h", "util.h"] {
+            write_file(path(dir.path(), name), *name).await.unwrap();
+        }
+
+        assert_eq!(
+            dep_build(dir.path(), &[]).await.unwrap(),
+            [Reason::MissingOutput]
+        );
+        let db = Database::default();
+        db.open(dir.path()).await.unwrap();
+        assert_eq!(
+            db.output(path(dir.path(), "main.o")).unwrap().discovered,
+            [path(dir.path(), "main.h"), path(dir.path(), "util.h")]
+        );
+
+        // the recorded inputs is restored and merged with known ones
+        assert!(dep_build(dir.path(), &[]).await.unwrap().is_empty());
+        assert!(dep_build(dir.path(), &["util.h"]).await.unwrap().is_empty());
+
+        let header = path(dir.path(), "main.h");
+        write_file(&header, "changed").await.unwrap();
+        crate::system::set_modified(&header, Time::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert!(matches!(
+            &dep_build(dir.path(), &[]).await.unwrap()[..],
+            [Reason::ChangedInput { input, .. }] if *input == header
+        ));
+        assert!(dep_build(dir.path(), &[]).await.unwrap().is_empty());
+
+        // the outputs which was produced without recording is outdated
+        crate::system::remove_file(dir.path().join(crate::DATABASE_FILE))
+            .await
+            .unwrap();
+        assert_eq!(
+            dep_build(dir.path(), &[]).await.unwrap(),
+            [Reason::UnknownInputs]
+        );
+    }
+
+    #[async_std::test]
+    async fn cached_discovered_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = path(dir.path(), "main.c");
+        let header = path(dir.path(), "main.h");
+        let obj = path(dir.path(), "main.o");
+        for name in &[&src, &header] {
+            write_file(name, "").await.unwrap();
+        }
+
+        // the rule reports discovered inputs only when invoked
+        let build = || async {
+            let store = ArtifactStore::default();
+            store.database.open(dir.path()).await.unwrap();
+            store.cache.open(dir.path().join("cache"), 0).await.unwrap();
+            let input = Artifact::<Input, Actual>::new_init(&store, src.clone(), "")
+                .await
+                .unwrap();
+            let output = Artifact::<Output, Actual>::new_init(&store, obj.clone(), "")
+                .await
+                .unwrap();
+            let reported = Artifact::<Input, Actual>::new_init(&store, header.clone(), "")
+                .await
+                .unwrap();
+            let rule = Rule::from_api(Ref::new(DepRule {
+                input: input.into_kind_any(),
+                output: output.clone().into_kind_any().weak(),
+                headers: Vec::new(),
+                reported: Some(vec![reported.into_kind_any()]),
+                discovered: Default::default(),
+            }));
+            output.set_rule(rule.clone()).unwrap();
+            rule.process(&store, false, &Cancel::default())
+                .await
+                .unwrap();
+            store.database.save().await.unwrap();
+            store
+        };
+
+        let store = build().await;
+        assert_eq!(store.cache.stats().stores, 1);
+        assert_eq!(
+            store.database.output(&obj).unwrap().discovered,
+            [header.clone()]
+        );
+
+        remove_file(&obj).await.unwrap();
+        remove_file(dir.path().join(crate::DATABASE_FILE))
+            .await
+            .unwrap();
+        // the inputs which was discovered by rule is restored with outputs
+        let store = build().await;
+        assert_eq!(store.cache.stats().hits, 1);
+        assert_eq!(
+            store.database.output(&obj).unwrap().discovered,
+            [header.clone()]
+        );
+    }
+
+    /// Declare the chain of rules `main.c -> main.o -> main` like a new gear process does
+    async fn chain(
+        dir: &std::path::Path,
+    ) -> Result<(ArtifactStore, Vec<Artifact<Output, Actual>>, Vec<Rule>)> {
+        let store = ArtifactStore::default();
+        store.database.open(dir).await?;
+        let mut input = Artifact::<Input, Actual>::new_init(&store, path(dir, "main.c"), "")
+            .await?
+            .into_kind_any();
+        let mut outputs = Vec::new();
+        let mut rules = Vec::new();
+        for (name, flag) in &[("main.o", "-c"), ("main", "-o")] {
+            let output = Artifact::<Output, Actual>::new_init(&store, path(dir, name), "").await?;
+            rules.push(declare(&input, &output, &[*flag])?);
+            input = output.input().into_kind_any();
+            outputs.push(output);
+        }
+        Ok((store, outputs, rules))
+    }
+
+    #[async_std::test]
+    async fn unchanged_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = path(dir.path(), "main.c");
+        write_file(&src, "int main() {}").await.unwrap();
+
+        let (store, _outputs, rules) = chain(dir.path()).await.unwrap();
+        for rule in &rules {
+            assert!(rule
+                .process(&store, false, &Cancel::default())
+                .await
+                .unwrap());
+        }
+        store.database.save().await.unwrap();
+
+        // the source is changed but the object will be the same
+        write_file(&src, "int main() { return 0; }").await.unwrap();
+        crate::system::set_modified(&src, Time::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+
+        let (store, outputs, rules) = chain(dir.path()).await.unwrap();
+        assert!(rules[0]
+            .process(&store, false, &Cancel::default())
+            .await
+            .unwrap());
+        assert!(!rules[1]
+            .process(&store, false, &Cancel::default())
+            .await
+            .unwrap());
+
+        // the real time of rewritten output is kept
+        let obj = &outputs[0];
+        assert_eq!(obj.time(), modified(Path::new(obj.name())).await.unwrap());
+        assert_eq!(store.database.output(obj.name()).unwrap().time, obj.time());
+    }
+
+    #[async_std::test]
+    async fn dependency_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = path(dir.path(), "main.c");
+        write_file(&src, "int main() {}").await.unwrap();
+
+        let schedule = |store: ArtifactStore, output: Artifact<Output, Actual>| async move {
+            let mut reasons = Vec::new();
+            output
+                .process(&store.database, &mut Map::default(), &mut |_, reason| {
+                    reasons.push(reason)
+                })
+                .await
+                .unwrap();
+            reasons
+        };
+
+        let (store, outputs, rules) = chain(dir.path()).await.unwrap();
+        assert_eq!(
+            schedule(store.clone(), outputs[1].clone()).await,
+            [
+                Reason::MissingOutput,
+                Reason::Dependency(outputs[0].name().clone())
+            ]
+        );
+        for rule in &rules {
+            rule.process(&store, false, &Cancel::default())
+                .await
+                .unwrap();
+        }
+        store.database.save().await.unwrap();
+
+        let (store, outputs, _rules) = chain(dir.path()).await.unwrap();
+        assert!(schedule(store, outputs[1].clone()).await.is_empty());
+
+        write_file(&src, "int main() { return 0; }").await.unwrap();
+        crate::system::set_modified(&src, Time::now() + Duration::from_secs(1))
+            .await
+            .unwrap();
+        let (store, outputs, _rules) = chain(dir.path()).await.unwrap();
+        let reasons = schedule(store, outputs[1].clone()).await;
+        assert!(matches!(
+            &reasons[..],
+            [Reason::ChangedInput { input, .. }, Reason::Dependency(dep)]
+                if *input == src && dep == outputs[0].name()
+        ));
+    }
+
+    #[test]
+    fn js_rule_signature() {
+        let rt = qjs::Runtime::new().unwrap();
+        let ctx = qjs::Context::full(&rt).unwrap();
+        // rules should be dropped outside of context
+        let mut rules = Vec::new();
+        let mut signature = |source: &str, keys: &[&str]| {
+            let store = ArtifactStore::default();
+            let output = Artifact::<Output, Actual>::new(&store, "out", "").unwrap();
+            let rule = ctx.with(|ctx| {
+                let function: qjs::Function = ctx.eval(source).unwrap();
+                JsRule::new_raw(
+                    Default::default(),
+                    once(output.clone().into_kind_any()).collect(),
+                    qjs::Persistent::save(ctx, function),
+                    ctx,
+                    RuleOptions {
+                        signature: keys.iter().map(|key| key.to_string()).collect(),
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+            });
+            let digest = rule.to_dyn().signature_digest();
+            rules.push((output, rule));
+            digest
+        };
+
+        let source = "(async function() { await exec({cmd: 'cc'}); })";
+        let base = signature(source, &["-O0"]);
+        assert_eq!(signature(source, &["-O0"]), base);
+        assert_ne!(signature(source, &["-O2"]), base);
+        assert_ne!(signature(source, &[]), base);
+        assert_ne!(
+            signature("(async function() { await exec({cmd: 'c++'}); })", &["-O0"]),
+            base
+        );
+        drop(rules);
+    }
+}
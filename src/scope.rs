@@ -1,12 +1,14 @@
 use crate::{
-    qjs, Artifact, ArtifactStore, Input, JsRule, Mut, NoRule, Output, Phony, Ref, Result, Set,
-    Store, Value, ValueDef, Variable, VariableStore,
+    qjs, Artifact, ArtifactStore, HistoryStats, Input, JsRule, Mut, NoRule, Output, Phony, Ref,
+    Result, RuleOptions, Set, Store, Value, ValueDef, Variable, VariableStore, HISTORY_BUILDS,
+    HISTORY_RULES,
 };
 use derive_deref::Deref;
 use either::Either;
 use std::{
     borrow::Borrow,
     fmt::{Display, Formatter, Result as FmtResult},
+    future::Future,
     hash::{Hash, Hasher},
     iter::once,
 };
@@ -199,10 +201,17 @@ impl Scope {
         description: impl AsRef<str>,
     ) -> Result<Artifact<Output, Phony>> {
         let goal = Artifact::new(self, self.full_name(name), description.as_ref())?;
+        goal.set_scope(self.name().clone());
         self.0.goals.write().insert(goal.clone());
         Ok(goal)
     }
 
+    /// Declare pool which limits number of rules processed simultaneously
+    pub fn new_pool(&self, name: impl Into<String>, depth: usize) -> Result<()> {
+        let artifacts: &ArtifactStore = self.0.store.as_ref();
+        artifacts.new_pool(name, depth)
+    }
+
     pub fn is_root(&self) -> bool {
         self.name().is_empty()
     }
@@ -319,15 +328,16 @@ mod js {
             description: String,
             function: qjs::Persistent<qjs::Function<'static>>,
             ctx: qjs::Ctx<'js>,
+            opts: qjs::Opt<RuleOptions>,
         ) -> Result<Goal<JsRule>> {
-            let context = qjs::Context::from_ctx(ctx)?;
             let artifact = self.new_goal(name, description)?;
             Ok(Goal(JsRule::new_raw(
                 Default::default(),
                 once(artifact.into_kind_any()).collect(),
                 function,
-                context,
-            )))
+                ctx,
+                opts.0.unwrap_or_default(),
+            )?))
         }
 
         #[doc(hidden)]
@@ -336,12 +346,34 @@ mod js {
             &self,
             name: String,
             description: qjs::Opt<String>,
+            opts: qjs::Opt<RuleOptions>,
         ) -> Result<Goal<NoRule>> {
             let artifact = self.new_goal(name, description.0.unwrap_or_default())?;
-            Ok(Goal(NoRule::new_raw(
+            Ok(Goal(NoRule::new_opts(
                 Default::default(),
                 once(artifact.into_kind_any()).collect(),
-            )))
+                opts.0.unwrap_or_default(),
+            )?))
+        }
+
+        /// Get statistics of last builds
+        #[quickjs(rename = "history")]
+        pub fn history_js(
+            &self,
+            builds: qjs::Opt<usize>,
+            limit: qjs::Opt<usize>,
+        ) -> qjs::Promised<impl Future<Output = Result<HistoryStats>>> {
+            let artifacts: &ArtifactStore = self.as_ref();
+            let history = artifacts.history.clone();
+            let builds = builds.0.unwrap_or(HISTORY_BUILDS);
+            let limit = limit.0.unwrap_or(HISTORY_RULES);
+            qjs::Promised(async move { history.stats(builds, limit).await })
+        }
+
+        #[doc(hidden)]
+        #[quickjs(rename = "pool")]
+        pub fn pool_js(&self, name: String, depth: usize) -> Result<()> {
+            self.new_pool(name, depth)
         }
 
         #[quickjs(rename = "toString")]
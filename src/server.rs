@@ -1,6 +1,6 @@
 use crate::Event;
 use async_std::{channel::Receiver, io::Cursor};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tide::{http::Url, sse, Body, Request};
 
 #[derive(Serialize)]
@@ -20,6 +20,7 @@ struct RulesMap {
 struct RuleStateChangeData {
     rule: String,
     state: gear::RuleState,
+    attempt: usize,
 }
 
 impl From<&gear::RuleStateChange> for RuleStateChangeData {
@@ -27,10 +28,17 @@ impl From<&gear::RuleStateChange> for RuleStateChangeData {
         Self {
             rule: event.rule.id().to_string(),
             state: event.state,
+            attempt: event.attempt,
         }
     }
 }
 
+#[derive(Deserialize)]
+struct HistoryQuery {
+    builds: Option<usize>,
+    limit: Option<usize>,
+}
+
 #[derive(Clone)]
 pub struct Server {
     receiver: Receiver<Event>,
@@ -82,6 +90,7 @@ impl Server {
         app.at("/bundle.js.map").get(Self::bundle_script_map);
 
         app.at("/rules").get(Self::rules);
+        app.at("/history").get(Self::history);
         app.at("/events").get(sse::endpoint(Self::events));
 
         let url = url.clone();
@@ -144,6 +153,22 @@ impl Server {
         Body::from_json(&output)
     }
 
+    async fn history(req: Request<Server>) -> tide::Result<Body> {
+        let query: HistoryQuery = req.query()?;
+        let state = req.state();
+        let store: &gear::ArtifactStore = state.scope.as_ref();
+
+        let stats = store
+            .history
+            .stats(
+                query.builds.unwrap_or(gear::HISTORY_BUILDS),
+                query.limit.unwrap_or(gear::HISTORY_RULES),
+            )
+            .await?;
+
+        Body::from_json(&stats)
+    }
+
     async fn events(req: Request<Server>, sender: sse::Sender) -> tide::Result<()> {
         let state = req.state();
         loop {
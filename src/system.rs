@@ -1,7 +1,10 @@
 mod common;
+mod sandbox;
 pub use common::*;
+pub use sandbox::{Sandbox, SandboxRoot};
 
-use crate::{qjs, Map};
+use crate::{qjs, Error, JsRule, Map, Result};
+use std::process::Command as StdCommand;
 
 #[derive(qjs::FromJs)]
 pub struct ExecArg {
@@ -14,6 +17,10 @@ pub struct ExecArg {
     pub cwd: Option<String>,
     #[quickjs(default)]
     pub input: Option<String>,
+    #[quickjs(default)]
+    pub sandbox: Option<Sandbox>,
+    #[quickjs(default)]
+    pub rule: Option<JsRule>,
 }
 
 #[derive(qjs::IntoJs)]
@@ -23,6 +30,81 @@ pub struct ExecRes {
     pub error: String,
 }
 
+/// Execute program using arguments from JS
+///
+/// When rule is passed its sandbox will be used by default and the program will be terminated on cancellation.
+/// The accesses to undeclared paths by failed program in sandbox will be reported.
+pub async fn exec(input: ExecArg) -> Result<ExecRes> {
+    let (rule, sandbox, cancel) = if let Some(rule) = &input.rule {
+        let invocation = rule.invocation();
+        (
+            invocation.rule,
+            input.sandbox.or(invocation.sandbox),
+            Some(invocation.cancel),
+        )
+    } else {
+        (input.cmd.clone(), input.sandbox, None)
+    };
+    // the root of sandbox should be kept until program completed
+    let (mut cmd, _root) = if let Some(sandbox) = &sandbox {
+        let (cmd, root) = sandbox.command(&input.cmd)?;
+        (cmd, Some(root))
+    } else {
+        (StdCommand::new(&input.cmd), None)
+    };
+    if let Some(args) = input.args {
+        cmd.args(args);
+    }
+    if let Some(envs) = input.envs {
+        cmd.envs(envs);
+    }
+    if let Some(cwd) = input.cwd {
+        cmd.current_dir(cwd);
+    }
+    let data = input.input;
+    let (mut handle, guard) = spawn_group(cmd, |cmd| {
+        if data.is_some() {
+            cmd.stdin(Stdio::piped());
+        }
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+    })
+    .map_err(|error| {
+        if sandbox.is_some() {
+            Sandbox::spawn_error(error)
+        } else {
+            error
+        }
+    })?;
+    let result = async move {
+        if let Some(data) = data {
+            if let Some(stdin) = &mut handle.stdin {
+                stdin.write_all(data.as_bytes()).await?;
+            } else {
+                return Err("Unable to write input".into());
+            }
+        }
+        handle.output().await.map_err(Error::from)
+    };
+    let result = if let Some(cancel) = &cancel {
+        cancel.run(result).await?
+    } else {
+        result.await
+    }?;
+    guard.disarm();
+    let status = result.status.code();
+    let output = String::from_utf8(result.stdout)?;
+    let error = String::from_utf8(result.stderr)?;
+    if let (false, Some(sandbox)) = (result.status.success(), &sandbox) {
+        sandbox.report_undeclared(&rule, &error);
+    }
+    Ok(ExecRes {
+        status,
+        output,
+        error,
+    })
+}
+
 #[qjs::bind(module, public)]
 #[quickjs(bare)]
 mod js {
@@ -69,38 +151,46 @@ mod js {
         )
     }
 
-    pub async fn exec(input: ExecArg) -> qjs::Result<ExecRes> {
-        let mut cmd = Command::new(input.cmd);
-        if let Some(args) = input.args {
-            cmd.args(args);
-        }
-        if let Some(envs) = input.envs {
-            cmd.envs(envs);
-        }
-        if let Some(cwd) = input.cwd {
-            cmd.current_dir(cwd);
-        }
-        if input.input.is_some() {
-            cmd.stdin(Stdio::piped());
-        }
-        cmd.stdout(Stdio::piped());
-        cmd.stderr(Stdio::piped());
-        let mut handle = cmd.spawn()?;
-        if let Some(input) = input.input {
-            if let Some(stdin) = &mut handle.stdin {
-                stdin.write_all(input.as_bytes()).await?;
-            } else {
-                return Err(qjs::Error::Unknown);
+    pub async fn exec(input: ExecArg) -> Result<ExecRes> {
+        super::exec(input).await
+    }
+}
+
+#[cfg(all(test, unix, feature = "ctrlc"))]
+mod test {
+    use super::*;
+    use nix::{sys::signal::kill, unistd::Pid};
+
+    #[allow(clippy::await_holding_lock)]
+    #[async_std::test]
+    async fn kill_on_interrupt() {
+        let _lock = common::PROCESS_LOCK
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        // the program is executed without rule so nothing can cancel it except interruption
+        let task = spawn(exec(ExecArg {
+            cmd: "sleep".into(),
+            args: Some(vec!["30".into()]),
+            envs: None,
+            cwd: None,
+            input: None,
+            sandbox: None,
+            rule: None,
+        }));
+        let pid = loop {
+            if let Some(pid) = common::PROCESS_GROUPS.lock().unwrap().first().copied() {
+                break pid;
             }
-        }
-        let result = handle.output().await?;
-        let status = result.status.code();
-        let output = String::from_utf8(result.stdout)?;
-        let error = String::from_utf8(result.stderr)?;
-        Ok(ExecRes {
-            status,
-            output,
-            error,
-        })
+            async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+        };
+
+        kill_process_groups();
+        let res = async_std::future::timeout(std::time::Duration::from_secs(5), task)
+            .await
+            .expect("Program is still running")
+            .unwrap();
+        assert_eq!(res.status, None);
+        assert!(kill(Pid::from_raw(pid as _), None).is_err());
+        assert!(common::PROCESS_GROUPS.lock().unwrap().is_empty());
     }
 }
@@ -1,13 +1,20 @@
-use crate::{Result, Time};
+use super::Sandbox;
+use crate::{Cancel, Result, Time};
 pub use async_std::{
-    fs::{create_dir_all, read as read_file, remove_file, write as write_file},
+    fs::{
+        copy, create_dir_all, read as read_file, remove_dir, remove_file, rename,
+        write as write_file,
+    },
     path::{Path, PathBuf},
     prelude::*,
-    process::{Command, ExitStatus, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
     task::{spawn_blocking, spawn_local as spawn},
 };
 use futures::future::join_all;
-use std::ffi::{OsStr, OsString};
+use std::{
+    ffi::{OsStr, OsString},
+    process::Command as StdCommand,
+};
 
 pub use faccess::AccessMode;
 pub use relative_path::*;
@@ -19,6 +26,19 @@ pub async fn modified(path: &Path) -> Result<Time> {
     Ok(time)
 }
 
+/// Set modified time
+pub async fn set_modified(path: impl AsRef<Path>, time: Time) -> Result<()> {
+    let path: std::path::PathBuf = path.as_ref().into();
+    spawn_blocking(move || {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_modified(time)
+    })
+    .await?;
+    Ok(())
+}
+
 /// Check access to path
 ///
 /// TODO: Switch to async version of `faccess` when it will be awailable.
@@ -98,10 +118,25 @@ impl ExecOut<ExitStatus> {
     }
 }
 
+/// The environment variables which is set for executed programs
+pub const EXEC_ENVS: &[(&str, &str)] = &[("LANG", "C"), ("LC_ALL", "C")];
+
 /// Simply execute an arbitrary program to collect output.
 pub async fn exec_out(
     cmd: impl AsRef<OsStr>,
     args: &[impl AsRef<OsStr>],
+) -> Result<ExecOut<ExitStatus>> {
+    exec_out_in(None, None, cmd, args).await
+}
+
+/// Execute an arbitrary program to collect output optionally in sandbox.
+///
+/// The program will be terminated with its children on cancellation.
+pub async fn exec_out_in(
+    sandbox: Option<&Sandbox>,
+    cancel: Option<&Cancel>,
+    cmd: impl AsRef<OsStr>,
+    args: &[impl AsRef<OsStr>],
 ) -> Result<ExecOut<ExitStatus>> {
     let cmd = cmd.as_ref();
 
@@ -112,12 +147,33 @@ pub async fn exec_out(
     }
 
     log::debug!("Exec `{}`", cmd_line);
-    let out = Command::new(cmd)
-        .args(args)
-        .env("LANG", "C")
-        .env("LC_ALL", "C")
-        .output()
-        .await?;
+    // the root of sandbox should be kept until program completed
+    let (mut command, _root) = if let Some(sandbox) = sandbox {
+        let (command, root) = sandbox.command(cmd)?;
+        (command, Some(root))
+    } else {
+        (StdCommand::new(cmd), None)
+    };
+    command.args(args).envs(EXEC_ENVS.iter().copied());
+    let (child, guard) = spawn_group(command, |command| {
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+    })
+    .map_err(|error| {
+        if sandbox.is_some() {
+            Sandbox::spawn_error(error)
+        } else {
+            error
+        }
+    })?;
+    let out = if let Some(cancel) = cancel {
+        cancel.run(child.output()).await?
+    } else {
+        child.output().await
+    }?;
+    guard.disarm();
     Ok(ExecOut {
         cmd: cmd_line,
         res: out.status,
@@ -126,6 +182,92 @@ pub async fn exec_out(
     })
 }
 
+/// Spawn program in separate process group
+///
+/// The returned guard terminates the whole group when it dropped before disarming,
+/// i.e. when the future which waits for program is dropped on cancellation.
+pub fn spawn_group(
+    #[cfg_attr(not(all(unix, feature = "ctrlc")), allow(unused_mut))] mut command: StdCommand,
+    setup: impl FnOnce(&mut Command),
+) -> Result<(Child, GroupGuard)> {
+    #[cfg(all(unix, feature = "ctrlc"))]
+    {
+        use nix::unistd::{setpgid, Pid};
+        use std::os::unix::process::CommandExt;
+
+        // Safety: setpgid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                setpgid(Pid::from_raw(0), Pid::from_raw(0))
+                    .map_err(|_| std::io::Error::last_os_error())
+            });
+        }
+    }
+    let mut command = Command::from(command);
+    command.kill_on_drop(true);
+    setup(&mut command);
+    let child = command.spawn()?;
+    #[cfg(all(unix, feature = "ctrlc"))]
+    PROCESS_GROUPS.lock().unwrap().push(child.id());
+    let guard = GroupGuard(Some(child.id()));
+    Ok((child, guard))
+}
+
+/// The process groups of programs which is running now
+///
+/// The futures which waits for programs may be never dropped (i.e. when it awaited by detached JS tasks)
+/// so the groups should be tracked globally to be terminated on interruption.
+#[cfg(all(unix, feature = "ctrlc"))]
+pub(crate) static PROCESS_GROUPS: std::sync::Mutex<Vec<u32>> = std::sync::Mutex::new(Vec::new());
+
+#[cfg(all(unix, feature = "ctrlc"))]
+fn kill_group(pid: u32) {
+    use nix::{
+        sys::signal::{killpg, Signal},
+        unistd::Pid,
+    };
+
+    log::debug!("Terminate process group {}", pid);
+    let _ = killpg(Pid::from_raw(pid as _), Signal::SIGTERM);
+}
+
+/// The lock for tests which should not be interfered by terminating process groups
+#[cfg(test)]
+pub(crate) static PROCESS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Terminate process groups of all running programs
+pub fn kill_process_groups() {
+    #[cfg(all(unix, feature = "ctrlc"))]
+    for pid in PROCESS_GROUPS.lock().unwrap().iter() {
+        kill_group(*pid);
+    }
+}
+
+/// The guard which terminates process group of program when dropped
+pub struct GroupGuard(Option<u32>);
+
+impl GroupGuard {
+    /// Do not terminate process group because program is completed
+    pub fn disarm(mut self) {
+        if let Some(_pid) = self.0.take() {
+            #[cfg(all(unix, feature = "ctrlc"))]
+            PROCESS_GROUPS.lock().unwrap().retain(|pid| *pid != _pid);
+        }
+    }
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        if let Some(_pid) = self.0.take() {
+            #[cfg(all(unix, feature = "ctrlc"))]
+            {
+                PROCESS_GROUPS.lock().unwrap().retain(|pid| *pid != _pid);
+                kill_group(_pid);
+            }
+        }
+    }
+}
+
 /// Temporary file which will be removed when handle is dropped
 pub struct TempFile {
     path: PathBuf,
@@ -188,8 +330,8 @@ impl TempFile {
 
         let mut rng = rand::thread_rng();
 
-        for i in 5..32 {
-            name[i] = *ALPHABET.iter().choose(&mut rng).unwrap();
+        for c in &mut name[5..] {
+            *c = *ALPHABET.iter().choose(&mut rng).unwrap();
         }
     }
 
@@ -0,0 +1,523 @@
+use crate::{qjs, Error, Result, Set};
+use std::{
+    env,
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+/// The system directories which is visible in sandbox
+///
+/// The accesses to undeclared paths inside them cannot be detected.
+const SYSTEM_DIRS: &[&str] = &["/bin", "/sbin", "/usr", "/lib", "/lib32", "/lib64", "/etc"];
+
+/// The directories which is visible and writable in sandbox
+const DEVICE_DIRS: &[&str] = &["/dev", "/proc"];
+
+/// The view of filesystem for sandboxed commands
+///
+/// Only inputs and system directories is visible read-only and only output directories is writable.
+#[derive(Debug, Clone, Default, qjs::FromJs, qjs::IntoJs)]
+pub struct Sandbox {
+    /// The paths which is visible read-only
+    pub inputs: Set<String>,
+    /// The directories which is writable
+    pub outputs: Set<String>,
+}
+
+/// The root directory of sandbox which will be removed when dropped
+///
+/// Each sandboxed command gets own root so concurrent rules does not interfere.
+pub struct SandboxRoot(PathBuf);
+
+impl Drop for SandboxRoot {
+    fn drop(&mut self) {
+        let path = self.0.clone();
+
+        super::spawn(async move {
+            if let Err(error) = super::remove_dir(&path).await {
+                log::warn!(
+                    "Unable to remove sandbox root `{}` due to: {}",
+                    path.display(),
+                    error
+                );
+            }
+        });
+    }
+}
+
+impl SandboxRoot {
+    /// Create new empty root in temporary directory
+    pub fn new() -> Result<Self> {
+        let dir = env::temp_dir();
+        let mut index = 0;
+        loop {
+            let path = dir.join(format!("gear-sandbox-{}-{}", std::process::id(), index));
+            match std::fs::create_dir(&path) {
+                Ok(_) => return Ok(Self(path)),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => index += 1,
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Get the path of root
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+/// Get absolute path without `.` and `..` components
+fn absolute(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let path = if path.is_absolute() {
+        path.into()
+    } else {
+        env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.into())
+    };
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+impl Sandbox {
+    /// Make path visible read-only
+    pub fn add_input(&mut self, path: impl AsRef<Path>) {
+        self.inputs.insert(absolute(path).display().to_string());
+    }
+
+    /// Make the directory of output writable
+    pub fn add_output(&mut self, path: impl AsRef<Path>) {
+        if let Some(dir) = absolute(path).parent() {
+            self.outputs.insert(dir.display().to_string());
+        }
+    }
+
+    /// Check that path is visible in sandbox
+    pub fn is_visible(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.inputs
+            .iter()
+            .chain(self.outputs.iter())
+            .map(|dir| dir.as_str())
+            .chain(SYSTEM_DIRS.iter().copied())
+            .chain(DEVICE_DIRS.iter().copied())
+            .any(|dir| path.starts_with(dir))
+    }
+
+    /// Get the paths which seems accessed by failed command but not visible in sandbox
+    ///
+    /// The error output is searched for paths which is reported as missing or inaccessible
+    /// while it exists outside of sandbox.
+    pub fn undeclared(&self, output: &str) -> Vec<String> {
+        let mut paths = Set::<String>::default();
+        for line in output.lines().filter(|line| {
+            line.contains("No such file or directory")
+                || line.contains("Permission denied")
+                || line.contains("Read-only file system")
+        }) {
+            for token in line.split(|c: char| {
+                c.is_whitespace()
+                    || matches!(c, ':' | ',' | '\'' | '"' | '`' | '‘' | '’' | '(' | ')')
+            }) {
+                if !token.contains('/') {
+                    continue;
+                }
+                let path = absolute(token);
+                if path.exists() && !self.is_visible(&path) {
+                    paths.insert(token.into());
+                }
+            }
+        }
+        paths.into_iter().collect()
+    }
+
+    /// Report accesses to undeclared paths by failed command of rule
+    pub fn report_undeclared(&self, rule: &str, output: &str) {
+        for path in self.undeclared(output) {
+            log::warn!(
+                "{} may access undeclared path `{}` which is not visible in sandbox",
+                rule,
+                path
+            );
+        }
+    }
+
+    /// Get the directories of tool which should be visible in sandbox
+    ///
+    /// The installation prefix is used when tool is placed into `bin` directory.
+    /// The real location is added too when tool is a symlink.
+    pub fn tool_dirs(tool: impl AsRef<OsStr>) -> Vec<String> {
+        let path = if let Ok(path) = which::which(tool.as_ref()) {
+            path
+        } else {
+            return Vec::new();
+        };
+        let mut dirs = Vec::new();
+        for path in std::iter::once(path.clone()).chain(path.canonicalize().ok()) {
+            if let Some(dir) = path.parent() {
+                let dir = if dir.file_name() == Some("bin".as_ref()) {
+                    dir.parent().unwrap_or(dir)
+                } else {
+                    dir
+                };
+                let dir = dir.display().to_string();
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        dirs
+    }
+
+    /// Convert the error of spawning sandboxed command to be more clear
+    pub fn spawn_error(error: Error) -> Error {
+        match error {
+            Error::Io(error) if error.kind() == ErrorKind::PermissionDenied => format!(
+                "Unable to setup sandbox due to: {}. Seems unprivileged user namespaces is not available (see `kernel.unprivileged_userns_clone` sysctl).",
+                error
+            )
+            .into(),
+            error => error,
+        }
+    }
+
+    /// Create command which will be executed in sandbox
+    ///
+    /// The directories of command is visible in sandbox too.
+    /// The returned root should be kept until command completed.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    pub fn command(&self, cmd: impl AsRef<OsStr>) -> Result<(Command, SandboxRoot)> {
+        use std::os::unix::process::CommandExt;
+
+        let cmd = cmd.as_ref();
+        let mut sandbox = self.clone();
+        for dir in Self::tool_dirs(cmd) {
+            sandbox.add_input(dir);
+        }
+        let root = SandboxRoot::new()?;
+        let mounts = sandbox.mounts();
+        let setup = linux::Setup::new(root.path().into(), &mounts)?;
+        let mut command = Command::new(cmd);
+        // Safety: the setup does not allocate memory and uses signal-safe calls only
+        unsafe {
+            command.pre_exec(move || setup.apply());
+        }
+        Ok((command, root))
+    }
+
+    /// Create command which will be executed in sandbox
+    #[cfg(not(all(target_os = "linux", feature = "sandbox")))]
+    pub fn command(&self, _cmd: impl AsRef<OsStr>) -> Result<(Command, SandboxRoot)> {
+        Err("Sandbox is not supported on this platform".into())
+    }
+
+    /// Get the paths which should be mounted with its writability
+    ///
+    /// The paths is ordered so parents is mounted before its children.
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    fn mounts(&self) -> Vec<(PathBuf, bool)> {
+        let mut mounts = SYSTEM_DIRS
+            .iter()
+            .map(|dir| (PathBuf::from(dir), false))
+            .chain(DEVICE_DIRS.iter().map(|dir| (PathBuf::from(dir), true)))
+            .chain(self.inputs.iter().map(|path| (PathBuf::from(path), false)))
+            .chain(self.outputs.iter().map(|path| (PathBuf::from(path), true)))
+            .filter(|(path, _)| path.exists())
+            .collect::<Vec<_>>();
+        mounts.sort();
+        let mut result: Vec<(PathBuf, bool)> = Vec::with_capacity(mounts.len());
+        for (path, writable) in mounts {
+            // skip paths which already mounted with the same access
+            if let Some((_, parent_writable)) = result
+                .iter()
+                .rev()
+                .find(|(parent, _)| path.starts_with(parent))
+            {
+                if *parent_writable == writable {
+                    continue;
+                }
+            }
+            result.push((path, writable));
+        }
+        result
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+mod linux {
+    use crate::Result;
+    use nix::{
+        errno::Errno,
+        fcntl::{open, OFlag},
+        mount::{mount, MsFlags},
+        sched::{unshare, CloneFlags},
+        sys::{
+            stat::{stat, Mode, SFlag},
+            statvfs::{statvfs, FsFlags},
+        },
+        unistd::{chdir, chroot, close, getgid, getuid, mkdir, write},
+        Error,
+    };
+    use std::{
+        env, io,
+        path::{Path, PathBuf},
+    };
+
+    /// The step of sandbox setup
+    enum Step {
+        /// Write contents to file
+        Write(PathBuf, String),
+        /// Make mounts private
+        Private,
+        /// Mount tmpfs
+        Tmpfs(PathBuf),
+        /// Bind path with its writability
+        Bind(PathBuf, PathBuf, bool),
+        /// Change root directory
+        Chroot(PathBuf),
+        /// Change current directory
+        Chdir(PathBuf),
+    }
+
+    /// The setup of sandbox which is applied in child process before executing command
+    ///
+    /// All paths and contents is prepared in parent process so applying does not allocate.
+    pub struct Setup {
+        steps: Vec<Step>,
+    }
+
+    fn to_io(error: Error) -> io::Error {
+        error
+            .as_errno()
+            .map(|errno| io::Error::from_raw_os_error(errno as i32))
+            .unwrap_or_else(|| io::ErrorKind::Other.into())
+    }
+
+    fn exists(path: &Path) -> bool {
+        stat(path).is_ok()
+    }
+
+    /// Create directory with its parents
+    fn create_dirs(path: &Path) -> nix::Result<()> {
+        if exists(path) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            create_dirs(parent)?;
+        }
+        match mkdir(path, Mode::from_bits_truncate(0o755)) {
+            Err(Error::Sys(Errno::EEXIST)) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Create empty file with its parents
+    fn create_file(path: &Path) -> nix::Result<()> {
+        if exists(path) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            create_dirs(parent)?;
+        }
+        let fd = open(
+            path,
+            OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_CLOEXEC,
+            Mode::from_bits_truncate(0o644),
+        )?;
+        close(fd)
+    }
+
+    fn write_file(path: &Path, data: &str) -> nix::Result<()> {
+        let fd = open(path, OFlag::O_WRONLY | OFlag::O_CLOEXEC, Mode::empty())?;
+        let result = write(fd, data.as_bytes());
+        close(fd)?;
+        result.map(|_| ())
+    }
+
+    /// Bind path and remount it read-only when needed
+    ///
+    /// The flags of source mount should be preserved on remount in user namespace.
+    fn bind(src: &Path, dst: &Path, writable: bool) -> nix::Result<()> {
+        if SFlag::from_bits_truncate(stat(src)?.st_mode & SFlag::S_IFMT.bits()) == SFlag::S_IFDIR {
+            create_dirs(dst)?;
+        } else {
+            create_file(dst)?;
+        }
+        mount(
+            Some(src),
+            dst,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )?;
+        if writable {
+            return Ok(());
+        }
+        let flags = statvfs(dst)?.flags();
+        let mut remount = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+        for (flag, mount_flag) in &[
+            (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+            (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+            (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+            (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+            (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+            (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+        ] {
+            if flags.contains(*flag) {
+                remount |= *mount_flag;
+            }
+        }
+        mount(None::<&str>, dst, None::<&str>, remount, None::<&str>)
+    }
+
+    impl Setup {
+        pub fn new(root: PathBuf, mounts: &[(PathBuf, bool)]) -> Result<Self> {
+            let uid = getuid();
+            let gid = getgid();
+            let cwd = env::current_dir()?;
+            let inner = |path: &Path| root.join(path.strip_prefix("/").unwrap_or(path));
+
+            let mut steps = vec![
+                Step::Write("/proc/self/setgroups".into(), "deny".into()),
+                Step::Write("/proc/self/uid_map".into(), format!("{} {} 1", uid, uid)),
+                Step::Write("/proc/self/gid_map".into(), format!("{} {} 1", gid, gid)),
+                Step::Private,
+                Step::Tmpfs(root.clone()),
+                Step::Tmpfs(inner(Path::new("/tmp"))),
+            ];
+            for (path, writable) in mounts {
+                steps.push(Step::Bind(path.clone(), inner(path), *writable));
+            }
+            steps.push(Step::Chroot(root.clone()));
+            steps.push(Step::Chdir(cwd));
+            Ok(Self { steps })
+        }
+
+        pub fn apply(&self) -> io::Result<()> {
+            unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS).map_err(to_io)?;
+            for step in &self.steps {
+                match step {
+                    Step::Write(path, data) => write_file(path, data),
+                    Step::Private => mount(
+                        None::<&str>,
+                        "/",
+                        None::<&str>,
+                        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+                        None::<&str>,
+                    ),
+                    Step::Tmpfs(path) => create_dirs(path).and_then(|_| {
+                        mount(
+                            Some("tmpfs"),
+                            path,
+                            Some("tmpfs"),
+                            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                            None::<&str>,
+                        )
+                    }),
+                    Step::Bind(src, dst, writable) => bind(src, dst, *writable),
+                    Step::Chroot(path) => chroot(path),
+                    Step::Chdir(path) => create_dirs(path).and_then(|_| chdir(path)),
+                }
+                .map_err(to_io)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn visibility() {
+        let mut sandbox = Sandbox::default();
+        sandbox.add_input("/src/./main.c");
+        sandbox.add_output("/build/../out/main.o");
+        assert!(sandbox.is_visible("/src/main.c"));
+        assert!(!sandbox.is_visible("/src/util.c"));
+        assert!(sandbox.is_visible("/out/main.d"));
+        assert!(sandbox.is_visible("/usr/include/stdio.h"));
+        assert!(!sandbox.is_visible("/home"));
+
+        let hidden = std::env::temp_dir().display().to_string();
+        let error = format!(
+            "cc: fatal error: {}: No such file or directory\ncc: error: /src/main.c: Permission denied",
+            hidden
+        );
+        assert_eq!(sandbox.undeclared(&error), [hidden]);
+        assert!(sandbox.undeclared("cc: error: /src/main.c").is_empty());
+
+        assert!(!Sandbox::tool_dirs("sh").is_empty());
+        assert!(Sandbox::tool_dirs("gear-missing-tool").is_empty());
+    }
+
+    #[cfg(all(target_os = "linux", feature = "sandbox"))]
+    #[allow(clippy::await_holding_lock)]
+    #[async_std::test]
+    async fn isolation() {
+        use crate::system::exec_out_in;
+
+        let _lock = super::super::common::PROCESS_LOCK
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        std::fs::write(path("input.txt"), "input").unwrap();
+        std::fs::write(path("hidden.txt"), "hidden").unwrap();
+        std::fs::create_dir(path("out")).unwrap();
+
+        let mut sandbox = Sandbox::default();
+        sandbox.add_input(path("input.txt"));
+        sandbox.add_output(path("out/result.txt"));
+        let exec = |script: String| {
+            let sandbox = sandbox.clone();
+            async move { exec_out_in(Some(&sandbox), None, "sh", &["-c", script.as_str()]).await }
+        };
+
+        let res = match exec(format!("cat {}", path("input.txt"))).await {
+            Ok(res) => res,
+            Err(error) if error.to_string().contains("Unable to setup sandbox") => {
+                // unprivileged user namespaces is not available
+                eprintln!("Skip sandbox test: {}", error);
+                return;
+            }
+            Err(error) => panic!("{}", error),
+        };
+        assert!(res.is_success());
+        assert_eq!(res.out, "input");
+
+        // undeclared inputs is not visible
+        let res = exec(format!("cat {}", path("hidden.txt"))).await.unwrap();
+        assert!(!res.is_success());
+        assert_eq!(sandbox.undeclared(&res.err), [path("hidden.txt")]);
+
+        // directories of outputs is writable
+        let res = exec(format!("echo result > {}", path("out/result.txt")))
+            .await
+            .unwrap();
+        assert!(res.is_success());
+        assert_eq!(
+            std::fs::read_to_string(path("out/result.txt")).unwrap(),
+            "result\n"
+        );
+
+        // other writes does not leak out of sandbox
+        let _ = exec(format!("echo leak > {}", path("leak.txt")))
+            .await
+            .unwrap();
+        assert!(!dir.path().join("leak.txt").exists());
+    }
+}
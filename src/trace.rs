@@ -0,0 +1,121 @@
+use crate::{
+    system::{create_dir_all, write_file, Path},
+    Mut, Result, Rule,
+};
+use serde::Serialize;
+use std::{iter::once, time::Instant};
+
+/// The complete event in Chrome Trace Event format
+#[derive(Serialize)]
+struct Event {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    /// Start time in microseconds
+    ts: u64,
+    /// Duration in microseconds
+    dur: u64,
+    pid: u32,
+    /// Job slot
+    tid: usize,
+    args: EventArgs,
+}
+
+#[derive(Serialize)]
+struct EventArgs {
+    id: String,
+    outputs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    success: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Data<'a> {
+    trace_events: &'a [Event],
+    display_time_unit: &'static str,
+}
+
+/// The timeline of build
+///
+/// Can be saved in Chrome Trace Event format to be loaded into Perfetto or `chrome://tracing`.
+pub struct Trace {
+    start: Instant,
+    events: Mut<Vec<Event>>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            events: Default::default(),
+        }
+    }
+}
+
+impl Trace {
+    /// Record rule processing
+    pub fn add_rule(&self, rule: &Rule, slot: usize, start: Instant, end: Instant, success: bool) {
+        let outputs = rule
+            .outputs()
+            .into_iter()
+            .map(|output| output.name().clone())
+            .collect::<Vec<_>>();
+        let name = if outputs.is_empty() {
+            rule.to_string()
+        } else {
+            outputs.join(", ")
+        };
+        let signature = rule.signature();
+        let command = if signature.cmd.is_empty() {
+            None
+        } else {
+            Some(
+                once(&signature.cmd)
+                    .chain(signature.args.iter())
+                    .map(|arg| arg.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        };
+        let ts = start.saturating_duration_since(self.start).as_micros() as u64;
+        let dur = end.saturating_duration_since(start).as_micros() as u64;
+
+        self.events.write().push(Event {
+            name,
+            cat: "rule",
+            ph: "X",
+            ts,
+            dur,
+            pid: 1,
+            tid: slot,
+            args: EventArgs {
+                id: rule.id().to_string(),
+                outputs,
+                command,
+                success,
+            },
+        });
+    }
+
+    /// Save trace to file
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        log::debug!("Save trace `{}`", path.display());
+        let raw = {
+            let events = self.events.read();
+            serde_json::to_vec(&Data {
+                trace_events: &events,
+                display_time_unit: "ms",
+            })?
+        };
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() && !dir.is_dir().await {
+                create_dir_all(dir).await?;
+            }
+        }
+        write_file(path, raw).await?;
+        Ok(())
+    }
+}
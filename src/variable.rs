@@ -12,6 +12,7 @@ pub use validation::Validator;
 pub use value::Value;
 
 use crate::{qjs, Map, Mut, Ref, Result, Weak, WeakElement, WeakKey, WeakSet};
+use serde::Serialize;
 
 use std::{
     borrow::Borrow,
@@ -20,6 +21,21 @@ use std::{
     hash::{Hash, Hasher},
 };
 
+/// The source of variable value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueSource {
+    /// The default value of variable
+    #[default]
+    Default,
+    /// The value from config file
+    Config,
+    /// The value from command line
+    Cli,
+    /// The value which is set by rules script
+    Script,
+}
+
 #[derive(Clone)]
 pub struct Variable(Ref<Internal>);
 
@@ -28,6 +44,16 @@ impl Variable {
         WeakVariable(Ref::downgrade(&self.0))
     }
 
+    /// Get the source of current value
+    pub fn source(&self) -> ValueSource {
+        *self.0.source.read()
+    }
+
+    fn assign(&self, value: Value, source: ValueSource) {
+        *self.0.value.write() = value;
+        *self.0.source.write() = source;
+    }
+
     pub fn fmt_tree(&self, ident: usize, f: &mut Formatter) -> FmtResult {
         let spaces = ident * 4;
         write!(f, "{:ident$}{}", "", self.name(), ident = spaces)?;
@@ -91,6 +117,7 @@ pub struct Internal {
     def: VariableDef,
     //validator: Option<Box<dyn Validator + Send + Sync>>,
     value: Mut<Value>,
+    source: Mut<ValueSource>,
 }
 
 impl Drop for Internal {
@@ -129,7 +156,8 @@ impl From<VariableDef> for Variable {
     fn from(def: VariableDef) -> Self {
         log::debug!("Variable::new `{}`", def.name);
         let value = Mut::new(def.default.clone());
-        Self(Ref::new(Internal { def, value }))
+        let source = Mut::new(ValueSource::Default);
+        Self(Ref::new(Internal { def, value, source }))
     }
 }
 
@@ -220,7 +248,7 @@ impl VariableStore {
                 );
             } else {
                 //value.coerce(&def.definition)
-                variable.set_value(value.clone());
+                variable.assign(value.clone(), ValueSource::Config);
             }
         }
 
@@ -236,7 +264,7 @@ impl VariableStore {
                         );
                     } else {
                         //value.coerce(&def.definition)
-                        variable.set_value(value.clone());
+                        variable.assign(value.clone(), ValueSource::Cli);
                     }
                 }
                 Err(error) => {
@@ -297,7 +325,7 @@ mod js {
 
         #[quickjs(set, rename = "value")]
         pub fn set_value(&self, value: Value) {
-            *self.0.value.write() = value;
+            self.assign(value, ValueSource::Script);
         }
 
         #[quickjs(rename = "toString")]
@@ -25,6 +25,17 @@ declare module "system" {
         cwd?: string;
         /// Data to feed via stdin
         input?: string;
+        /// Execute in sandbox (use `this.sandbox` of rule)
+        sandbox?: Sandbox;
+        /// Rule which executes program (its sandbox is used by default and program will be terminated on cancellation)
+        rule?: import("gear").AnyRule;
+    }
+
+    export interface Sandbox {
+        /// Paths which is visible read-only
+        inputs: string[];
+        /// Directories which is writable
+        outputs: string[];
     }
 
     export interface ExecResult {